@@ -0,0 +1,40 @@
+-- A cert-manager Certificate with a custom health check
+
+local metadata = {
+    name = 'example-com',
+}
+
+local spec = {
+    secretName = 'example-com-tls',
+    dnsNames = {
+        'example.com',
+    },
+    issuerRef = {
+        name = 'letsencrypt',
+        kind = 'ClusterIssuer',
+    },
+}
+
+-- Receives the live object, returns one of healthy, progressing or
+-- degraded along with a message
+local function health(obj)
+    local conditions = obj.status and obj.status.conditions or {}
+    for _, c in ipairs(conditions) do
+        if c.type == 'Ready' then
+            if c.status == 'True' then
+                return 'healthy', c.message
+            elseif c.reason == 'Failed' then
+                return 'degraded', c.message
+            end
+        end
+    end
+    return 'progressing', 'waiting for certificate to be issued'
+end
+
+return {
+    apiVersion = 'cert-manager.io/v1',
+    kind = 'Certificate',
+    metadata = metadata,
+    spec = spec,
+    __health = health,
+}
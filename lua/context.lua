@@ -0,0 +1,19 @@
+-- Render differently depending on the kubeconfig context being targeted
+
+local namespace = 'staging'
+if kluars.context == 'prod' then
+    namespace = 'production'
+end
+
+return {
+    apiVersion = 'v1',
+    kind = 'ConfigMap',
+    metadata = {
+        name = 'cluster-info',
+        namespace = namespace,
+    },
+    data = {
+        context = kluars.context,
+        cluster = kluars.cluster,
+    },
+}
@@ -0,0 +1,20 @@
+-- Deploy the same configuration to multiple clusters, keyed by kubeconfig
+-- context through kluars.clusters
+
+local function config(region)
+    return {
+        apiVersion = 'v1',
+        kind = 'ConfigMap',
+        metadata = {
+            name = 'app-config',
+        },
+        data = {
+            region = region,
+        },
+    }
+end
+
+return kluars.clusters {
+    ['prod-us'] = { config('us') },
+    ['prod-eu'] = { config('eu') },
+}
@@ -0,0 +1,22 @@
+-- Container images need a pinned tag
+
+local function containers(doc)
+    local spec = doc.spec or {}
+    if doc.kind ~= 'Pod' then
+        spec = spec.template and spec.template.spec or {}
+    end
+    return spec.containers or {}
+end
+
+return {
+    ['no-latest-tag'] = function(doc)
+        local violations = {}
+        for _, c in ipairs(containers(doc)) do
+            local image = c.image or ''
+            if not image:find(':') or image:match(':latest$') then
+                table.insert(violations, 'container ' .. c.name .. ' uses image ' .. image .. ' without a pinned tag')
+            end
+        end
+        return violations
+    end,
+}
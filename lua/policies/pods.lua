@@ -0,0 +1,31 @@
+-- Restrictions on the pods run in the cluster
+
+local function pod_spec(doc)
+    if doc.kind == 'Pod' then
+        return doc.spec
+    end
+    return doc.spec and doc.spec.template and doc.spec.template.spec
+end
+
+return {
+    ['no-host-network'] = function(doc)
+        local spec = pod_spec(doc)
+        if spec and spec.hostNetwork then
+            return 'pods must not use the host network'
+        end
+    end,
+
+    ['resource-limits'] = {
+        severity = 'warn',
+        check = function(doc)
+            local spec = pod_spec(doc)
+            local violations = {}
+            for _, c in ipairs(spec and spec.containers or {}) do
+                if not (c.resources and c.resources.limits) then
+                    table.insert(violations, 'container ' .. c.name .. ' has no resource limits')
+                end
+            end
+            return violations
+        end,
+    },
+}
@@ -0,0 +1,21 @@
+-- The nginx pod built with the k8s module, apiVersion and kind are filled
+-- in and unknown fields are rejected
+
+local k8s = require('k8s')
+
+return k8s.core.v1.Pod {
+    metadata = {
+        name = 'nginx',
+    },
+    spec = {
+        containers = {
+            {
+                name = 'nginx',
+                image = 'nginx:1.14.2',
+                ports = {
+                    { containerPort = 80, }
+                },
+            },
+        },
+    },
+}
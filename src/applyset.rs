@@ -0,0 +1,288 @@
+//! Minimal implementation of ApplySets as described in KEP-3659
+//!
+//! A Secret is used as the parent object, it keeps track of the group kinds
+//! and namespaces of every object that is part of the set, so objects no
+//! longer rendered by the lua configuration can be found and pruned.
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashSet},
+};
+
+use anyhow::Result;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use k8s_openapi::api::core::v1::Secret;
+use kube::{
+    api::{Api, DeleteParams, DynamicObject, ListParams, Patch, PatchParams},
+    core::{GroupVersionKind, ObjectMeta, TypeMeta},
+    discovery::Scope,
+    Client, Discovery, ResourceExt,
+};
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+
+use crate::{dynamic_api, order};
+
+const PART_OF_LABEL: &str = "applyset.kubernetes.io/part-of";
+const ID_LABEL: &str = "applyset.kubernetes.io/id";
+const TOOLING_ANNOTATION: &str = "applyset.kubernetes.io/tooling";
+const GROUP_KINDS_ANNOTATION: &str = "applyset.kubernetes.io/contains-group-kinds";
+const NAMESPACES_ANNOTATION: &str = "applyset.kubernetes.io/additional-namespaces";
+
+/// Identifies an object that is a member of the ApplySet
+#[derive(PartialEq, Eq, Hash)]
+pub(crate) struct Member {
+    pub group_kind: String,
+    pub namespace: Option<String>,
+    pub name: String,
+}
+
+/// Format a GVK the way ApplySet annotations expect it, i.e. `Kind.group`
+pub(crate) fn group_kind(gvk: &GroupVersionKind) -> String {
+    if gvk.group.is_empty() {
+        gvk.kind.clone()
+    } else {
+        format!("{}.{}", gvk.kind, gvk.group)
+    }
+}
+
+/// Split a group kind formatted by [`group_kind`] into kind and group
+fn kind(gk: &str) -> (&str, &str) {
+    gk.split_once('.').unwrap_or((gk, ""))
+}
+
+/// Format an object listed by the ApplySet as `Kind.group namespace/name`
+pub(crate) fn path(obj: &DynamicObject) -> String {
+    let gk = obj
+        .types
+        .as_ref()
+        .and_then(|t| GroupVersionKind::try_from(t).ok())
+        .map(|gvk| group_kind(&gvk))
+        .unwrap_or_default();
+    match obj.namespace() {
+        Some(ns) => format!("{gk} {ns}/{}", obj.name_any()),
+        None => format!("{gk} {}", obj.name_any()),
+    }
+}
+
+/// Split a list stored in an annotation, ignoring empty entries
+fn split_annotation(secret: &Secret, key: &str) -> BTreeSet<String> {
+    secret
+        .annotations()
+        .get(key)
+        .map(|v| {
+            v.split(',')
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn join(set: &BTreeSet<String>) -> String {
+    set.iter().cloned().collect::<Vec<_>>().join(",")
+}
+
+pub(crate) struct ApplySet {
+    name: String,
+    namespace: String,
+    id: String,
+    field_manager: String,
+    api: Api<Secret>,
+    group_kinds: BTreeSet<String>,
+    namespaces: BTreeSet<String>,
+}
+
+impl ApplySet {
+    /// Create an ApplySet, loading the members tracked by a previous run
+    /// from its parent, if it exists
+    pub(crate) async fn new(
+        client: &Client,
+        name: &str,
+        namespace: Option<&str>,
+        field_manager: &str,
+    ) -> Result<Self> {
+        let namespace = namespace.unwrap_or(client.default_namespace());
+        let hash = Sha256::digest(format!("{name}.{namespace}.Secret."));
+        let id = format!("applyset-{}-v1", URL_SAFE_NO_PAD.encode(hash));
+        let api: Api<Secret> = Api::namespaced(client.clone(), namespace);
+
+        let (group_kinds, namespaces) = match api.get_opt(name).await? {
+            Some(parent) => {
+                if parent.labels().get(ID_LABEL) != Some(&id) {
+                    anyhow::bail!("Secret {namespace}/{name} is not a valid ApplySet parent");
+                }
+                (
+                    split_annotation(&parent, GROUP_KINDS_ANNOTATION),
+                    split_annotation(&parent, NAMESPACES_ANNOTATION),
+                )
+            }
+            None => Default::default(),
+        };
+
+        Ok(ApplySet {
+            name: name.to_string(),
+            namespace: namespace.to_string(),
+            id,
+            field_manager: field_manager.to_string(),
+            api,
+            group_kinds,
+            namespaces,
+        })
+    }
+
+    /// Add the label marking an object as part of the ApplySet
+    pub(crate) fn label(&self, data: &mut serde_json::Value) {
+        let metadata = &mut data["metadata"];
+        if !metadata["labels"].is_object() {
+            metadata["labels"] = serde_json::json!({});
+        }
+        metadata["labels"][PART_OF_LABEL] = self.id.clone().into();
+    }
+
+    /// Store the group kinds and namespaces of the set in the parent
+    async fn update_parent(
+        &self,
+        group_kinds: &BTreeSet<String>,
+        namespaces: &BTreeSet<String>,
+    ) -> Result<()> {
+        let mut namespaces = namespaces.clone();
+        namespaces.remove(&self.namespace);
+
+        let parent = Secret {
+            metadata: ObjectMeta {
+                name: Some(self.name.clone()),
+                namespace: Some(self.namespace.clone()),
+                labels: Some(BTreeMap::from([(ID_LABEL.into(), self.id.clone())])),
+                annotations: Some(BTreeMap::from([
+                    (
+                        TOOLING_ANNOTATION.into(),
+                        format!("kluars/{}", env!("CARGO_PKG_VERSION")),
+                    ),
+                    (GROUP_KINDS_ANNOTATION.into(), join(group_kinds)),
+                    (NAMESPACES_ANNOTATION.into(), join(&namespaces)),
+                ])),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut data = serde_json::to_value(parent)?;
+        data["apiVersion"] = "v1".into();
+        data["kind"] = "Secret".into();
+
+        let ssapply = PatchParams::apply(&self.field_manager).force();
+        self.api
+            .patch(&self.name, &ssapply, &Patch::Apply(data))
+            .await?;
+        Ok(())
+    }
+
+    /// Record the members about to be applied on top of the ones from the
+    /// previous run, so they can still be pruned if the apply fails midway
+    pub(crate) async fn begin(&self, members: &HashSet<Member>) -> Result<()> {
+        let (group_kinds, namespaces) = self.with(members);
+        self.update_parent(&group_kinds, &namespaces).await
+    }
+
+    /// Merge the group kinds and namespaces of the members into the ones
+    /// known from the previous run
+    fn with(&self, members: &HashSet<Member>) -> (BTreeSet<String>, BTreeSet<String>) {
+        let mut group_kinds = self.group_kinds.clone();
+        let mut namespaces = self.namespaces.clone();
+        namespaces.insert(self.namespace.clone());
+        for m in members {
+            group_kinds.insert(m.group_kind.clone());
+            if let Some(ns) = &m.namespace {
+                namespaces.insert(ns.clone());
+            }
+        }
+        (group_kinds, namespaces)
+    }
+
+    /// Delete every object in the set that is not in `members`, then update
+    /// the parent to only track the current members
+    pub(crate) async fn prune(
+        &self,
+        client: &Client,
+        discovery: &Discovery,
+        members: &HashSet<Member>,
+        dry_run: bool,
+    ) -> Result<Vec<DynamicObject>> {
+        let (group_kinds, namespaces) = self.with(members);
+        let lp = ListParams::default().labels(&format!("{PART_OF_LABEL}={}", self.id));
+        let dp = DeleteParams::default();
+        let mut pruned = Vec::new();
+
+        // Prune in the reverse order objects are applied in, so custom
+        // resources go before their CRDs and objects before their namespace
+        let mut group_kinds: Vec<_> = group_kinds.iter().collect();
+        group_kinds.sort_by_key(|gk| Reverse(order::rank(kind(gk).0)));
+
+        for gk in group_kinds {
+            let (kind, group) = kind(gk);
+            let Some((ar, caps)) = discovery.get(group).and_then(|g| g.recommended_kind(kind))
+            else {
+                warn!("Cannot prune objects of unknown kind {gk}");
+                continue;
+            };
+
+            let scopes: Vec<Option<&str>> = if caps.scope == Scope::Cluster {
+                vec![None]
+            } else {
+                namespaces.iter().map(|ns| Some(ns.as_str())).collect()
+            };
+
+            for ns in scopes {
+                let api = dynamic_api(ar.clone(), caps.clone(), client.clone(), ns, false);
+                let list = match api.list(&lp).await {
+                    Ok(list) => list,
+                    Err(kube::Error::Api(e)) if e.code == 404 => {
+                        info!("{gk} is already gone, nothing to prune");
+                        continue;
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                for mut obj in list {
+                    let member = Member {
+                        group_kind: gk.clone(),
+                        namespace: obj.namespace(),
+                        name: obj.name_any(),
+                    };
+                    if members.contains(&member) {
+                        continue;
+                    }
+
+                    // Items in a list don't carry their own type
+                    obj.types = Some(TypeMeta {
+                        api_version: ar.api_version.clone(),
+                        kind: ar.kind.clone(),
+                    });
+                    if !dry_run {
+                        match api.delete(&member.name, &dp).await {
+                            Ok(_) => info!("pruned {}", path(&obj)),
+                            Err(kube::Error::Api(e)) if e.code == 404 => {
+                                info!("{} is already gone", path(&obj));
+                                continue;
+                            }
+                            Err(e) => return Err(e.into()),
+                        }
+                    }
+                    pruned.push(obj);
+                }
+            }
+        }
+
+        if !dry_run {
+            let (group_kinds, namespaces) = self.current(members);
+            self.update_parent(&group_kinds, &namespaces).await?;
+        }
+        Ok(pruned)
+    }
+
+    /// Group kinds and namespaces of the current members only
+    fn current(&self, members: &HashSet<Member>) -> (BTreeSet<String>, BTreeSet<String>) {
+        let group_kinds = members.iter().map(|m| m.group_kind.clone()).collect();
+        let namespaces = members.iter().filter_map(|m| m.namespace.clone()).collect();
+        (group_kinds, namespaces)
+    }
+}
@@ -0,0 +1,123 @@
+//! Creation of k8s clients from kubeconfig options
+
+use std::time::Duration;
+
+use anyhow::Result;
+use http::{header::RETRY_AFTER, HeaderName, HeaderValue, Response};
+use kube::{
+    client::ClientBuilder,
+    config::{KubeConfigOptions, Kubeconfig},
+    Client, Config,
+};
+use tower::{util::MapResultLayer, BoxError};
+use tower_http::set_header::SetRequestHeaderLayer;
+
+use crate::{config::KubeArgs, retry::Throttled};
+
+/// Header used for impersonating a UID, not supported by kube's `AuthInfo`
+const IMPERSONATE_UID: HeaderName = HeaderName::from_static("impersonate-uid");
+
+fn kubeconfig(args: &KubeArgs) -> Result<Kubeconfig> {
+    let kubeconfig = match &args.kubeconfig {
+        Some(path) => Kubeconfig::read_from(path)?,
+        None => Kubeconfig::read()?,
+    };
+    Ok(kubeconfig)
+}
+
+fn options(args: &KubeArgs) -> KubeConfigOptions {
+    KubeConfigOptions {
+        context: args.context.clone(),
+        cluster: args.cluster.clone(),
+        user: args.user.clone(),
+    }
+}
+
+/// Build the client configuration, falling back to the usual inference
+/// (KUBECONFIG, ~/.kube/config or in-cluster) when no kubeconfig option is
+/// set.
+async fn config(args: &KubeArgs) -> Result<Config> {
+    let options = options(args);
+    let mut config = if args.kubeconfig.is_none()
+        && options.context.is_none()
+        && options.cluster.is_none()
+        && options.user.is_none()
+    {
+        Config::infer().await?
+    } else {
+        Config::from_custom_kubeconfig(kubeconfig(args)?, &options).await?
+    };
+
+    if let Some(user) = &args.impersonate {
+        config.auth_info.impersonate = Some(user.clone());
+        config.auth_info.impersonate_groups = Some(args.impersonate_groups.clone());
+    }
+    if let Some(timeout) = args.request_timeout {
+        // Like kubectl, a zero timeout means waiting forever
+        let timeout = Some(timeout).filter(|t| !t.is_zero());
+        config.connect_timeout = timeout;
+        config.read_timeout = timeout;
+        config.write_timeout = timeout;
+    }
+    if args.insecure_skip_tls_verify {
+        config.accept_invalid_certs = true;
+        config.root_cert = None;
+    }
+    Ok(config)
+}
+
+/// Turn responses asking to retry later into errors carrying the delay,
+/// kube drops the response headers when building its own errors
+fn throttle<B>(res: Result<Response<B>, BoxError>) -> Result<Response<B>, BoxError> {
+    let res = res?;
+    let code = res.status().as_u16();
+    if code != 429 && code != 503 {
+        return Ok(res);
+    }
+
+    let retry_after = res
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok());
+    match retry_after {
+        Some(secs) => Err(Box::new(Throttled {
+            code,
+            retry_after: Duration::from_secs(secs),
+        })),
+        None => Ok(res),
+    }
+}
+
+pub(crate) async fn client(args: &KubeArgs) -> Result<Client> {
+    let builder =
+        ClientBuilder::try_from(config(args).await?)?.with_layer(&MapResultLayer::new(throttle));
+    let client = match &args.impersonate_uid {
+        Some(uid) => {
+            let value = HeaderValue::from_str(uid)?;
+            let layer = SetRequestHeaderLayer::overriding(IMPERSONATE_UID, value);
+            builder.with_layer(&layer).build()
+        }
+        None => builder.build(),
+    };
+    Ok(client)
+}
+
+/// Get the names of the context and cluster being targeted, if a
+/// kubeconfig is available.
+pub(crate) fn target(args: &KubeArgs) -> (Option<String>, Option<String>) {
+    let Ok(kubeconfig) = kubeconfig(args) else {
+        return (args.context.clone(), args.cluster.clone());
+    };
+
+    let context = args.context.clone().or(kubeconfig.current_context);
+    let cluster = args.cluster.clone().or_else(|| {
+        kubeconfig
+            .contexts
+            .into_iter()
+            .find(|c| Some(&c.name) == context.as_ref())?
+            .context
+            .map(|c| c.cluster)
+    });
+    (context, cluster)
+}
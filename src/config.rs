@@ -1,6 +1,7 @@
-use std::{error::Error, path::PathBuf};
+use std::{error::Error, num::NonZeroUsize, path::PathBuf, time::Duration};
 
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use kube::api::PatchParams;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about= None)]
@@ -23,19 +24,216 @@ pub struct LuaArgs {
     pub values: Option<PathBuf>,
 }
 
+#[derive(Args, Clone)]
+pub struct KubeArgs {
+    /// Path to the kubeconfig file to use
+    #[arg(long)]
+    pub kubeconfig: Option<PathBuf>,
+
+    /// Name of the kubeconfig context to use
+    #[arg(long)]
+    pub context: Option<String>,
+
+    /// Name of the kubeconfig cluster to use, ignored for documents whose
+    /// context is chosen by the lua script
+    #[arg(long)]
+    pub cluster: Option<String>,
+
+    /// Name of the kubeconfig user to use, ignored for documents whose
+    /// context is chosen by the lua script
+    #[arg(long)]
+    pub user: Option<String>,
+
+    /// Username to impersonate for the operation
+    #[arg(long = "as")]
+    pub impersonate: Option<String>,
+
+    /// Group to impersonate for the operation, can be used multiple times
+    #[arg(long = "as-group", requires = "impersonate")]
+    pub impersonate_groups: Vec<String>,
+
+    /// UID to impersonate for the operation
+    #[arg(long = "as-uid", requires = "impersonate")]
+    pub impersonate_uid: Option<String>,
+
+    /// Maximum time to wait for a single request to the server, e.g. 30s,
+    /// plain numbers are seconds and 0 disables the timeout
+    #[arg(long, value_parser = parse_timeout)]
+    pub request_timeout: Option<Duration>,
+
+    /// Don't verify the server certificate, making HTTPS connections insecure
+    #[arg(long)]
+    pub insecure_skip_tls_verify: bool,
+}
+
 #[derive(Args)]
 pub struct Global {
     /// k8s namespace to be used
     #[arg(short, long)]
     pub namespace: Option<String>,
 
-    /// Unused
-    #[arg(short = 'A', long = "all-namespaces")]
+    /// Operate on objects in all namespaces, documents applied with this
+    /// option need to have an explicit namespace
+    #[arg(short = 'A', long = "all-namespaces", conflicts_with = "namespace")]
     pub all: bool,
 
+    /// Check documents against the schema of built-in k8s types before
+    /// doing anything with them
+    #[arg(long)]
+    pub validate: bool,
+
+    /// Directory holding lua policy rules every document is checked against
+    #[arg(long)]
+    pub policy: Option<PathBuf>,
+
     /// Arguments to pass into lua
     #[command(flatten)]
     pub lua_args: LuaArgs,
+
+    /// Options for connecting to the k8s cluster
+    #[command(flatten)]
+    pub kube: KubeArgs,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DryRun {
+    /// Only resolve and validate documents against API discovery
+    Client,
+    /// Send the requests to the API server without persisting them
+    Server,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Output {
+    Json,
+    Yaml,
+}
+
+/// Options shared by the commands doing server-side apply
+#[derive(Args)]
+pub struct ServerSideArgs {
+    /// Name of the manager used to track field ownership
+    #[arg(long, default_value = crate::FIELD_MANAGER)]
+    pub field_manager: String,
+
+    /// Take ownership of fields managed by others instead of failing
+    #[arg(long)]
+    pub force_conflicts: bool,
+}
+
+impl ServerSideArgs {
+    /// Parameters for server-side applying with these options
+    pub fn params(&self) -> PatchParams {
+        let ssapply = PatchParams::apply(&self.field_manager);
+        if self.force_conflicts {
+            ssapply.force()
+        } else {
+            ssapply
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct ApplyArgs {
+    #[command(flatten)]
+    pub global: Global,
+
+    #[command(flatten)]
+    pub server_side: ServerSideArgs,
+
+    /// Number of independent documents to apply at the same time
+    #[arg(long, default_value = "1")]
+    pub concurrency: NonZeroUsize,
+
+    /// Number of times to retry a document after a transient API error
+    #[arg(long, default_value_t = 3)]
+    pub retries: u32,
+
+    /// Initial time between retries, doubled on every attempt, e.g. 500ms
+    #[arg(long, default_value = "500ms", value_parser = humantime::parse_duration)]
+    pub retry_backoff: Duration,
+
+    /// Keep applying the remaining documents when one of them fails
+    #[arg(long)]
+    pub continue_on_error: bool,
+
+    /// Fail if any document has a resource type unknown to the cluster
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Delete objects from previous applies that are no longer rendered
+    #[arg(long, requires = "applyset")]
+    pub prune: bool,
+
+    /// Name of the Secret used as the ApplySet parent for pruning
+    #[arg(long)]
+    pub applyset: Option<String>,
+
+    /// Don't persist any changes, only show what would be applied and pruned
+    #[arg(long, value_enum, num_args = 0..=1, require_equals = true, default_missing_value = "client")]
+    pub dry_run: Option<DryRun>,
+
+    /// Wait for applied objects to become ready
+    #[arg(long)]
+    pub wait: bool,
+
+    /// Maximum time to wait for objects to become ready, e.g. 30s or 5m
+    #[arg(long, default_value = "5m", value_parser = humantime::parse_duration)]
+    pub timeout: Duration,
+
+    /// Print the result of applying every object in the given format
+    #[arg(short, long, value_enum)]
+    pub output: Option<Output>,
+}
+
+/// Mirrors the propagation policies supported by kubectl
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Cascade {
+    /// Delete dependents in the background
+    Background,
+    /// Wait for dependents to be deleted before deleting the owner
+    Foreground,
+    /// Leave dependents behind
+    Orphan,
+}
+
+#[derive(Args)]
+pub struct DeleteArgs {
+    #[command(flatten)]
+    pub global: Global,
+
+    /// Wait for objects to be gone before deleting the next group
+    #[arg(long)]
+    pub wait: bool,
+
+    /// How dependents of deleted objects are handled
+    #[arg(long, value_enum, default_value_t = Cascade::Background)]
+    pub cascade: Cascade,
+
+    /// Maximum time to wait for objects to be gone, e.g. 30s or 5m
+    #[arg(long, default_value = "5m", value_parser = humantime::parse_duration)]
+    pub timeout: Duration,
+}
+
+#[derive(Args)]
+pub struct DiffArgs {
+    #[command(flatten)]
+    pub global: Global,
+
+    #[command(flatten)]
+    pub server_side: ServerSideArgs,
+}
+
+#[derive(Args)]
+pub struct ValidateArgs {
+    #[command(flatten)]
+    pub global: Global,
+
+    /// CRD manifest, or directory holding them, to check custom resources
+    /// against, use `cluster` to load the CRDs from the cluster. Can be used
+    /// multiple times
+    #[arg(long)]
+    pub crd: Vec<String>,
 }
 
 #[derive(Subcommand)]
@@ -43,7 +241,22 @@ pub enum Commands {
     /// Translate lua scripts to YAML
     Xlate(Global),
     /// Apply lua configuration to k8s cluster
-    Apply(Global),
+    Apply(ApplyArgs),
+    /// Show differences between lua configuration and k8s cluster
+    Diff(DiffArgs),
+    /// Delete every object in the lua configuration from k8s cluster
+    Delete(DeleteArgs),
+    /// Check the documents rendered by lua scripts without applying them
+    Validate(ValidateArgs),
+}
+
+/// Parse a timeout the way kubectl does, accepting plain seconds besides
+/// durations with units
+fn parse_timeout(s: &str) -> Result<Duration, humantime::DurationError> {
+    match s.parse() {
+        Ok(secs) => Ok(Duration::from_secs(secs)),
+        Err(_) => humantime::parse_duration(s),
+    }
 }
 
 // Shamelessly stolen from:
@@ -0,0 +1,150 @@
+//! Reporting of field conflicts returned by server-side apply
+
+use std::fmt::Write;
+
+/// A field owned by a different field manager
+struct Conflict {
+    manager: String,
+    field: String,
+}
+
+/// Extract the conflicts from the message returned by the API server.
+///
+/// Messages look like the following:
+/// ```text
+/// Apply failed with 2 conflicts: conflicts with "hpa" using apps/v1:
+/// - .spec.replicas
+/// - .spec.template.spec.containers[name="nginx"].image
+/// conflict with "kubectl" using apps/v1: .metadata.labels.app
+/// ```
+fn parse(message: &str) -> Vec<Conflict> {
+    let mut conflicts = Vec::new();
+    let mut manager = None;
+
+    for line in message.lines() {
+        if let Some(field) = line.strip_prefix("- ") {
+            if let Some(manager) = &manager {
+                conflicts.push(Conflict {
+                    manager: String::from(manager),
+                    field: field.to_string(),
+                });
+            }
+            continue;
+        }
+
+        let Some((_, rest)) = line.split_once(" with \"") else {
+            continue;
+        };
+        let Some((name, rest)) = rest.split_once('"') else {
+            continue;
+        };
+        manager = Some(name.to_string());
+
+        // Single conflicts have the field in the same line
+        if let Some((_, field)) = rest.split_once(": ") {
+            conflicts.push(Conflict {
+                manager: name.to_string(),
+                field: field.to_string(),
+            });
+        }
+    }
+    conflicts
+}
+
+/// Build a human readable report of the conflicts for an object
+pub(crate) fn report(kind: &str, name: &str, message: &str) -> String {
+    let conflicts = parse(message);
+    if conflicts.is_empty() {
+        return format!("Apply of {kind} {name} failed due to conflicts: {message}");
+    }
+
+    let mut out = format!("Apply of {kind} {name} failed due to field conflicts:\n");
+    for Conflict { manager, field } in conflicts {
+        let _ = writeln!(out, "  {field} is owned by \"{manager}\"");
+    }
+    out += "Use --force-conflicts to take ownership of these fields";
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conflicts(message: &str) -> Vec<(String, String)> {
+        parse(message)
+            .into_iter()
+            .map(|c| (c.manager, c.field))
+            .collect()
+    }
+
+    #[test]
+    fn single() {
+        let message = r#"Apply failed with 1 conflict: conflict with "kubectl" using apps/v1: .spec.replicas"#;
+        assert_eq!(
+            conflicts(message),
+            [("kubectl".to_string(), ".spec.replicas".to_string())]
+        );
+    }
+
+    #[test]
+    fn multiple() {
+        let message = "Apply failed with 2 conflicts: conflicts with \"hpa\" using apps/v1:\n\
+                       - .spec.replicas\n\
+                       - .spec.template.spec.containers[name=\"nginx\"].image";
+        assert_eq!(
+            conflicts(message),
+            [
+                ("hpa".to_string(), ".spec.replicas".to_string()),
+                (
+                    "hpa".to_string(),
+                    ".spec.template.spec.containers[name=\"nginx\"].image".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn several_managers() {
+        let message = "Apply failed with 3 conflicts: conflicts with \"hpa\" using apps/v1:\n\
+                       - .spec.replicas\n\
+                       - .spec.minReadySeconds\n\
+                       conflict with \"kubectl\" using apps/v1: .metadata.labels.app";
+        assert_eq!(
+            conflicts(message),
+            [
+                ("hpa".to_string(), ".spec.replicas".to_string()),
+                ("hpa".to_string(), ".spec.minReadySeconds".to_string()),
+                ("kubectl".to_string(), ".metadata.labels.app".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn subresource() {
+        let message = "Apply failed with 1 conflict: conflict with \"hpa\" with subresource \
+                       \"scale\" using apps/v1: .spec.replicas";
+        assert_eq!(
+            conflicts(message),
+            [("hpa".to_string(), ".spec.replicas".to_string())]
+        );
+
+        let message = "Apply failed with 2 conflicts: conflicts with \"controller\" with \
+                       subresource \"status\" using v1:\n\
+                       - .status.phase\n\
+                       - .status.message";
+        assert_eq!(
+            conflicts(message),
+            [
+                ("controller".to_string(), ".status.phase".to_string()),
+                ("controller".to_string(), ".status.message".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_format() {
+        let message = "Operation cannot be fulfilled: the object has been modified";
+        assert!(conflicts(message).is_empty());
+        assert!(report("Deployment", "web", message).contains(message));
+    }
+}
@@ -0,0 +1,44 @@
+//! Helpers for CustomResourceDefinitions applied alongside their instances
+
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
+use kube::{Api, Client};
+use log::debug;
+
+/// Time between checks on CRDs that are not established yet
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+fn established(crd: &CustomResourceDefinition) -> bool {
+    crd.status
+        .as_ref()
+        .and_then(|s| s.conditions.as_ref())
+        .map(|conditions| {
+            conditions
+                .iter()
+                .any(|c| c.type_ == "Established" && c.status == "True")
+        })
+        .unwrap_or(false)
+}
+
+/// Wait until all the given CRDs are ready to serve their custom resources
+pub(crate) async fn wait_established(
+    client: &Client,
+    names: &[String],
+    timeout: Duration,
+) -> Result<()> {
+    let api: Api<CustomResourceDefinition> = Api::all(client.clone());
+    let deadline = Instant::now() + timeout;
+
+    for name in names {
+        while !established(&api.get(name).await?) {
+            if Instant::now() >= deadline {
+                bail!("Timed out waiting for CustomResourceDefinition {name} to be established");
+            }
+            debug!("Waiting for CustomResourceDefinition {name}");
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,108 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use kube::{
+    api::{DeleteParams, DynamicObject},
+    Api, Discovery,
+};
+use log::{info, warn};
+use mlua::Lua;
+
+use crate::{
+    check, client, clusters,
+    config::{Cascade, DeleteArgs},
+    order, run_lua, set_env, Cluster, Document,
+};
+
+/// Time between checks while waiting for an object to go away
+const WAIT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Wait for an object to go away, failing once the deadline is reached
+async fn wait_deleted(
+    api: &Api<DynamicObject>,
+    kind: &str,
+    name: &str,
+    deadline: Instant,
+) -> Result<()> {
+    while api.get_opt(name).await?.is_some() {
+        if Instant::now() >= deadline {
+            bail!("Timed out waiting for {kind} {name} to be deleted");
+        }
+        tokio::time::sleep(WAIT_INTERVAL).await;
+    }
+    Ok(())
+}
+
+/// Delete every object rendered by the lua configuration, in the reverse
+/// order they would be applied.
+pub(crate) async fn delete(args: DeleteArgs) -> Result<()> {
+    set_env(&args.global.lua_args);
+    let lua = Lua::new();
+    let table = run_lua(&lua, &args.global)?;
+
+    let clusters = clusters(&lua, table, &args.global.kube)?;
+    check(&lua, &clusters, &args.global)?;
+
+    for cluster in clusters {
+        delete_cluster(&args, cluster).await?;
+    }
+    Ok(())
+}
+
+async fn delete_cluster(args: &DeleteArgs, cluster: Cluster<'_>) -> Result<()> {
+    let client = client::client(&cluster.kube).await?;
+    let discovery = Discovery::new(client.clone()).run().await?;
+    let dp = match args.cascade {
+        Cascade::Background => DeleteParams::background(),
+        Cascade::Foreground => DeleteParams::foreground(),
+        Cascade::Orphan => DeleteParams::orphan(),
+    };
+
+    let docs = cluster
+        .documents
+        .into_iter()
+        .map(|t| Document::new(t, &args.global))
+        .collect::<Result<Vec<_>>>()?;
+    let mut tiers = order::sort(docs)?;
+    tiers.reverse();
+
+    // Objects in the same tier are deleted together, waiting for all of
+    // them to be gone before moving on to the next tier if requested
+    for tier in &tiers {
+        let mut deleted = Vec::new();
+        for doc in tier.iter().rev() {
+            if doc.generated {
+                warn!(
+                    "Cannot delete {} {} without a name, it uses generateName",
+                    doc.gvk.kind, doc.name
+                );
+                continue;
+            }
+            if discovery.resolve_gvk(&doc.gvk).is_none() {
+                warn!("Cannot delete document for unknown {:?}", doc.gvk);
+                continue;
+            }
+
+            for api in doc.apis(&args.global, &client, &discovery).await? {
+                match api.delete(&doc.name, &dp).await {
+                    Ok(_) => {
+                        info!("deleted {} {}", doc.gvk.kind, doc.name);
+                        deleted.push((api, doc));
+                    }
+                    Err(kube::Error::Api(e)) if e.code == 404 => {
+                        info!("{} {} not found", doc.gvk.kind, doc.name);
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+
+        if args.wait {
+            let deadline = Instant::now() + args.timeout;
+            for (api, doc) in deleted {
+                wait_deleted(&api, &doc.gvk.kind, &doc.name, deadline).await?;
+            }
+        }
+    }
+    Ok(())
+}
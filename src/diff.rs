@@ -0,0 +1,236 @@
+use std::io::{stdout, IsTerminal};
+
+use anyhow::Result;
+use kube::{
+    api::{DynamicObject, Patch, PatchParams},
+    Api, Discovery, ResourceExt,
+};
+use log::{trace, warn};
+use mlua::Lua;
+use serde_json::{Map, Value};
+use similar::TextDiff;
+
+use crate::{
+    check, client, clusters, config::DiffArgs, conflicts, run_lua, set_env, Cluster, Document,
+};
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const CYAN: &str = "\x1b[36m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Serialize an object for diffing, dropping fields that only add noise
+fn to_yaml(obj: Option<DynamicObject>) -> Result<String> {
+    let Some(mut obj) = obj else {
+        return Ok(String::new());
+    };
+    obj.metadata.managed_fields = None;
+    Ok(serde_yaml::to_string(&obj)?)
+}
+
+/// Replace the values of a Secret with placeholders like `kubectl diff`
+/// does, only telling whether each of them changed
+fn mask_secret(live: &mut Option<DynamicObject>, merged: &mut DynamicObject) {
+    for field in ["data", "stringData"] {
+        let before = live
+            .as_ref()
+            .and_then(|l| l.data.get(field).cloned())
+            .unwrap_or_default();
+        let after = merged.data.get(field).cloned().unwrap_or_default();
+
+        let mask = |values: &Value, other: &Value, changed: &str| -> Value {
+            let Some(values) = values.as_object() else {
+                return values.clone();
+            };
+            values
+                .iter()
+                .map(|(k, v)| {
+                    let mask = if other.get(k) == Some(v) {
+                        "***".to_string()
+                    } else {
+                        format!("*** ({changed})")
+                    };
+                    (k.clone(), Value::String(mask))
+                })
+                .collect::<Map<_, _>>()
+                .into()
+        };
+
+        if let Some(live) = live.as_mut().filter(|_| !before.is_null()) {
+            live.data[field] = mask(&before, &after, "before");
+        }
+        if !after.is_null() {
+            merged.data[field] = mask(&after, &before, "after");
+        }
+    }
+}
+
+fn colorize(line: &str) -> String {
+    let color = if line.starts_with("---") || line.starts_with("+++") {
+        BOLD
+    } else if line.starts_with('-') {
+        RED
+    } else if line.starts_with('+') {
+        GREEN
+    } else if line.starts_with("@@") {
+        CYAN
+    } else {
+        return line.to_string();
+    };
+    format!("{color}{line}{RESET}")
+}
+
+async fn diff_single(
+    doc: &Document<'_>,
+    api: &Api<DynamicObject>,
+    ssapply: &PatchParams,
+) -> Result<String> {
+    let Document {
+        table, gvk, name, ..
+    } = doc;
+
+    trace!("Diffing {}: \n{}", gvk.kind, serde_yaml::to_string(&table)?);
+    let mut live = api.get_opt(name).await?;
+    let data: Value = serde_json::to_value(table)?;
+    let mut merged = match api.patch(name, ssapply, &Patch::Apply(data)).await {
+        Ok(merged) => merged,
+        Err(kube::Error::Api(e)) if e.code == 409 && !ssapply.force => {
+            anyhow::bail!(conflicts::report(&gvk.kind, name, &e.message));
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if gvk.group.is_empty() && gvk.kind == "Secret" {
+        mask_secret(&mut live, &mut merged);
+    }
+
+    let path = match merged.namespace() {
+        Some(ns) => format!("{}/{}/{}", gvk.kind, ns, name),
+        None => format!("{}/{}", gvk.kind, name),
+    };
+    let live = to_yaml(live)?;
+    let merged = to_yaml(Some(merged))?;
+
+    Ok(TextDiff::from_lines(&live, &merged)
+        .unified_diff()
+        .header(&format!("live/{path}"), &format!("merged/{path}"))
+        .to_string())
+}
+
+/// Show the differences between the live objects in the cluster and the
+/// result of applying the lua configuration to them.
+pub(crate) async fn diff(args: DiffArgs) -> Result<()> {
+    set_env(&args.global.lua_args);
+    let lua = Lua::new();
+    let table = run_lua(&lua, &args.global)?;
+
+    let clusters = clusters(&lua, table, &args.global.kube)?;
+    check(&lua, &clusters, &args.global)?;
+
+    for cluster in clusters {
+        diff_cluster(&args, cluster).await?;
+    }
+    Ok(())
+}
+
+async fn diff_cluster(args: &DiffArgs, cluster: Cluster<'_>) -> Result<()> {
+    let client = client::client(&cluster.kube).await?;
+    let discovery = Discovery::new(client.clone()).run().await?;
+    let ssapply = args.server_side.params().dry_run();
+    let color = stdout().is_terminal();
+
+    for table in cluster.documents {
+        let doc = Document::new(table, &args.global)?;
+        if doc.generated {
+            anyhow::bail!(
+                "{} {} uses generateName and cannot be server-side applied",
+                doc.gvk.kind,
+                doc.name
+            );
+        }
+        if discovery.resolve_gvk(&doc.gvk).is_none() {
+            warn!("Cannot diff document for unknown {:?}", doc.gvk);
+            continue;
+        }
+
+        let apis = doc.apis(&args.global, &client, &discovery).await?;
+        if apis.is_empty() {
+            warn!(
+                "{} {} matches no object in any namespace, set its namespace to see \
+                 what would be created",
+                doc.gvk.kind, doc.name
+            );
+        }
+        for api in apis {
+            let out = diff_single(&doc, &api, &ssapply).await?;
+            for line in out.lines() {
+                if color {
+                    println!("{}", colorize(line));
+                } else {
+                    println!("{line}");
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn secret(data: Value) -> DynamicObject {
+        serde_json::from_value(json!({
+            "apiVersion": "v1",
+            "kind": "Secret",
+            "metadata": { "name": "creds" },
+            "data": data,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn masks_secret() {
+        let mut live = Some(secret(json!({
+            "same": "YQ==",
+            "changed": "Yg==",
+            "removed": "Yw==",
+        })));
+        let mut merged = secret(json!({
+            "same": "YQ==",
+            "changed": "ZA==",
+            "added": "ZQ==",
+        }));
+        mask_secret(&mut live, &mut merged);
+
+        assert_eq!(
+            live.unwrap().data["data"],
+            json!({
+                "same": "***",
+                "changed": "*** (before)",
+                "removed": "*** (before)",
+            })
+        );
+        assert_eq!(
+            merged.data["data"],
+            json!({
+                "same": "***",
+                "changed": "*** (after)",
+                "added": "*** (after)",
+            })
+        );
+    }
+
+    #[test]
+    fn masks_new_secret() {
+        let mut live = None;
+        let mut merged = secret(json!({ "password": "YQ==" }));
+        mask_secret(&mut live, &mut merged);
+
+        assert!(live.is_none());
+        assert_eq!(merged.data["data"], json!({ "password": "*** (after)" }));
+        assert!(merged.data.get("stringData").is_none());
+    }
+}
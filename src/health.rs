@@ -0,0 +1,312 @@
+//! Readiness checks for objects after they are applied
+
+use std::{
+    fmt,
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use kube::{api::DynamicObject, Api, ResourceExt};
+use log::debug;
+use mlua::{Function, Lua, LuaSerdeExt};
+use serde_json::Value;
+
+/// Time between checks on objects that are not ready yet
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Health {
+    Healthy,
+    Progressing,
+    Failed,
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Health::Healthy => write!(f, "healthy"),
+            Health::Progressing => write!(f, "progressing"),
+            Health::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+pub(crate) struct Status {
+    pub health: Health,
+    pub message: String,
+}
+
+impl Status {
+    fn new(health: Health, message: impl Into<String>) -> Self {
+        Status {
+            health,
+            message: message.into(),
+        }
+    }
+}
+
+/// An applied object to be watched until it is ready
+pub(crate) struct Target<'lua> {
+    pub api: Api<DynamicObject>,
+    pub kind: String,
+    pub name: String,
+    /// Custom lua health check replacing the one for `kind`
+    pub check: Option<Function<'lua>>,
+}
+
+fn int(value: &Value, pointer: &str) -> i64 {
+    value.pointer(pointer).and_then(Value::as_i64).unwrap_or(0)
+}
+
+/// Find the status of a condition in `status.conditions`
+fn condition<'a>(obj: &'a DynamicObject, kind: &str) -> Option<(&'a str, &'a str)> {
+    obj.data
+        .pointer("/status/conditions")?
+        .as_array()?
+        .iter()
+        .find(|c| c["type"] == kind)
+        .map(|c| {
+            (
+                c["status"].as_str().unwrap_or_default(),
+                c["message"].as_str().unwrap_or_default(),
+            )
+        })
+}
+
+/// Whether the controller has caught up with the latest spec
+fn observed(obj: &DynamicObject) -> bool {
+    match obj.metadata.generation {
+        Some(generation) => int(&obj.data, "/status/observedGeneration") >= generation,
+        None => true,
+    }
+}
+
+fn replicas(obj: &DynamicObject) -> i64 {
+    obj.data
+        .pointer("/spec/replicas")
+        .and_then(Value::as_i64)
+        .unwrap_or(1)
+}
+
+fn deployment(obj: &DynamicObject) -> Status {
+    if let Some(("False", msg)) = condition(obj, "Progressing") {
+        return Status::new(Health::Failed, msg);
+    }
+    if !observed(obj) {
+        return Status::new(
+            Health::Progressing,
+            "waiting for spec update to be observed",
+        );
+    }
+
+    let want = replicas(obj);
+    let updated = int(&obj.data, "/status/updatedReplicas");
+    let available = int(&obj.data, "/status/availableReplicas");
+    let total = int(&obj.data, "/status/replicas");
+    if updated < want {
+        Status::new(
+            Health::Progressing,
+            format!("{updated} of {want} updated replicas"),
+        )
+    } else if total > updated {
+        Status::new(
+            Health::Progressing,
+            format!("{} old replicas pending termination", total - updated),
+        )
+    } else if available < want {
+        Status::new(
+            Health::Progressing,
+            format!("{available} of {want} updated replicas available"),
+        )
+    } else {
+        Status::new(Health::Healthy, format!("{available} replicas available"))
+    }
+}
+
+fn stateful_set(obj: &DynamicObject) -> Status {
+    if !observed(obj) {
+        return Status::new(
+            Health::Progressing,
+            "waiting for spec update to be observed",
+        );
+    }
+    if obj.data.pointer("/spec/updateStrategy/type") == Some(&"OnDelete".into()) {
+        return Status::new(Health::Healthy, "OnDelete update strategy");
+    }
+
+    let want = replicas(obj);
+    let ready = int(&obj.data, "/status/readyReplicas");
+    let updated = int(&obj.data, "/status/updatedReplicas");
+    let current = obj.data.pointer("/status/currentRevision");
+    let update = obj.data.pointer("/status/updateRevision");
+    if ready < want {
+        Status::new(
+            Health::Progressing,
+            format!("{ready} of {want} replicas ready"),
+        )
+    } else if updated < want || current != update {
+        Status::new(
+            Health::Progressing,
+            format!("{updated} of {want} replicas updated"),
+        )
+    } else {
+        Status::new(Health::Healthy, format!("{ready} replicas ready"))
+    }
+}
+
+fn daemon_set(obj: &DynamicObject) -> Status {
+    if !observed(obj) {
+        return Status::new(
+            Health::Progressing,
+            "waiting for spec update to be observed",
+        );
+    }
+
+    let want = int(&obj.data, "/status/desiredNumberScheduled");
+    let updated = int(&obj.data, "/status/updatedNumberScheduled");
+    let available = int(&obj.data, "/status/numberAvailable");
+    if updated < want {
+        Status::new(
+            Health::Progressing,
+            format!("{updated} of {want} pods updated"),
+        )
+    } else if available < want {
+        Status::new(
+            Health::Progressing,
+            format!("{available} of {want} pods available"),
+        )
+    } else {
+        Status::new(Health::Healthy, format!("{available} pods available"))
+    }
+}
+
+fn job(obj: &DynamicObject) -> Status {
+    if let Some(("True", msg)) = condition(obj, "Failed") {
+        Status::new(Health::Failed, msg)
+    } else if let Some(("True", _)) = condition(obj, "Complete") {
+        Status::new(Health::Healthy, "job completed")
+    } else {
+        Status::new(Health::Progressing, "job running")
+    }
+}
+
+fn pod(obj: &DynamicObject) -> Status {
+    match obj.data.pointer("/status/phase").and_then(Value::as_str) {
+        Some("Failed") => Status::new(Health::Failed, "pod failed"),
+        Some("Succeeded") => Status::new(Health::Healthy, "pod succeeded"),
+        _ => match condition(obj, "Ready") {
+            Some(("True", _)) => Status::new(Health::Healthy, "pod ready"),
+            _ => Status::new(Health::Progressing, "pod not ready"),
+        },
+    }
+}
+
+/// Fallback for kinds without a dedicated check, custom resources
+/// usually report their state through a Ready or Available condition
+fn generic(obj: &DynamicObject) -> Status {
+    match condition(obj, "Ready").or_else(|| condition(obj, "Available")) {
+        Some(("True", _)) => Status::new(Health::Healthy, "ready"),
+        Some((_, msg)) => Status::new(Health::Progressing, msg),
+        None => Status::new(Health::Healthy, "no readiness conditions"),
+    }
+}
+
+/// Evaluate the readiness of an object based on its kind
+pub(crate) fn evaluate(kind: &str, obj: &DynamicObject) -> Status {
+    match kind {
+        "Deployment" => deployment(obj),
+        "StatefulSet" => stateful_set(obj),
+        "DaemonSet" => daemon_set(obj),
+        "Job" => job(obj),
+        "Pod" => pod(obj),
+        _ => generic(obj),
+    }
+}
+
+/// Run a custom health check, it receives the live object and returns
+/// one of `healthy`, `progressing` or `degraded` along with a message.
+fn custom(lua: &Lua, check: &Function, obj: &DynamicObject) -> Result<Status> {
+    let (health, message): (String, Option<String>) = check.call(lua.to_value(obj)?)?;
+    let health = match health.as_str() {
+        "healthy" => Health::Healthy,
+        "progressing" => Health::Progressing,
+        "degraded" => Health::Failed,
+        _ => anyhow::bail!("Invalid health '{health}' returned for {}", obj.name_any()),
+    };
+    Ok(Status::new(health, message.unwrap_or_default()))
+}
+
+/// Wait for all targets to become healthy, giving up when one of them
+/// fails or the timeout expires. Returns the last known status of every
+/// target.
+pub(crate) async fn wait(
+    lua: &Lua,
+    targets: &[Target<'_>],
+    timeout: Duration,
+) -> Result<Vec<Status>> {
+    let deadline = Instant::now() + timeout;
+    let mut statuses: Vec<Status> = targets
+        .iter()
+        .map(|_| Status::new(Health::Progressing, "waiting"))
+        .collect();
+
+    loop {
+        for (target, status) in targets.iter().zip(statuses.iter_mut()) {
+            if status.health != Health::Progressing {
+                continue;
+            }
+            // Objects can go away while waiting, e.g. Jobs with a TTL
+            let Some(obj) = target.api.get_opt(&target.name).await? else {
+                *status = Status::new(Health::Failed, "not found");
+                continue;
+            };
+            *status = match &target.check {
+                Some(check) => custom(lua, check, &obj)?,
+                None => evaluate(&target.kind, &obj),
+            };
+            debug!(
+                "{} {}: {} {}",
+                target.kind, target.name, status.health, status.message
+            );
+        }
+
+        let progressing = statuses.iter().any(|s| s.health == Health::Progressing);
+        let failed = statuses.iter().any(|s| s.health == Health::Failed);
+        if !progressing || failed || Instant::now() >= deadline {
+            return Ok(statuses);
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Write a table with the status of every target
+pub(crate) fn report(
+    targets: &[Target<'_>],
+    statuses: &[Status],
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let rows: Vec<_> = targets
+        .iter()
+        .zip(statuses)
+        .map(|(t, s)| {
+            (
+                format!("{}/{}", t.kind, t.name),
+                s.health.to_string(),
+                &s.message,
+            )
+        })
+        .collect();
+    let width = rows
+        .iter()
+        .map(|(name, ..)| name.len())
+        .max()
+        .unwrap_or(0)
+        .max("RESOURCE".len());
+
+    writeln!(out, "{:width$}  {:11}  MESSAGE", "RESOURCE", "STATUS")?;
+    for (name, health, message) in rows {
+        writeln!(out, "{name:width$}  {health:11}  {message}")?;
+    }
+    Ok(())
+}
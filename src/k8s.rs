@@ -0,0 +1,98 @@
+//! The `k8s` lua module, with constructors for the types in k8s-openapi
+
+use mlua::{Lua, Table};
+
+use crate::{schema, SOURCES_KEY};
+
+/// Builds the module tree, constructors fill in apiVersion and kind and
+/// raise an error on the caller's line if the object doesn't match its type
+const MODULE: &str = r#"
+local validate, locate, types = ...
+local k8s = {}
+for _, t in ipairs(types) do
+    k8s[t.group] = k8s[t.group] or {}
+    local group = k8s[t.group]
+    group[t.version] = group[t.version] or {}
+    group[t.version][t.kind] = function(obj)
+        obj = obj or {}
+        obj.apiVersion = t.apiVersion
+        obj.kind = t.kind
+        local err = validate(obj)
+        if err then
+            error(err, 2)
+        end
+        locate(obj)
+        return obj
+    end
+end
+package.preload.k8s = function()
+    return k8s
+end
+"#;
+
+/// Name of the module holding a group, e.g. `apps` for `apps/v1` and
+/// `networking` for `networking.k8s.io/v1`
+fn module(group: &str) -> &str {
+    match group.split('.').next() {
+        Some("") | None => "core",
+        Some(name) => name,
+    }
+}
+
+/// Check a table against its type, returning the issues found as a single
+/// message
+fn check(lua: &Lua, table: Table) -> mlua::Result<Option<String>> {
+    let doc = schema::document(lua, &table)?;
+    let issues = schema::validate(&doc).unwrap_or_default();
+    if issues.is_empty() {
+        return Ok(None);
+    }
+    let kind = doc["kind"].as_str().unwrap_or_default();
+    let issues: Vec<String> = issues.iter().map(|i| i.to_string()).collect();
+    Ok(Some(format!("invalid {kind}: {}", issues.join(", "))))
+}
+
+/// Remember the file and line an object was built at, so diagnostics can
+/// point at it
+fn locate(lua: &Lua, table: Table) -> mlua::Result<()> {
+    // 0 is this function, 1 the constructor and 2 its caller
+    let Some(caller) = lua.inspect_stack(2) else {
+        return Ok(());
+    };
+    let line = caller.curr_line();
+    let Some(file) = caller
+        .source()
+        .source
+        .and_then(|s| s.strip_prefix('@').map(String::from))
+    else {
+        return Ok(());
+    };
+    if line <= 0 {
+        return Ok(());
+    }
+
+    let sources: Table = lua.named_registry_value(SOURCES_KEY)?;
+    if !sources.contains_key(table.clone())? {
+        sources.set(table, format!("{file}:{line}"))?;
+    }
+    Ok(())
+}
+
+/// Make the `k8s` module available to scripts through `require`
+pub(crate) fn preload(lua: &Lua) -> mlua::Result<()> {
+    let types = lua.create_table()?;
+    for ty in schema::TYPES {
+        let t = lua.create_table()?;
+        t.set("apiVersion", ty.api_version)?;
+        t.set("group", module(ty.group))?;
+        t.set("version", ty.version)?;
+        t.set("kind", ty.kind)?;
+        types.push(t)?;
+    }
+
+    let validate = lua.create_function(check)?;
+    let locate = lua.create_function(locate)?;
+    lua.load(MODULE)
+        .set_name("k8s module")
+        .call::<_, ()>((validate, locate, types))
+}
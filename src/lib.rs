@@ -1,30 +1,116 @@
-use std::fs;
+use std::{collections::HashSet, fs, io, time::Instant};
 
 use anyhow::Result;
-use config::{Cli, Global, LuaArgs};
+use applyset::{group_kind, ApplySet, Member};
+use config::{ApplyArgs, Cli, DryRun, Global, KubeArgs, LuaArgs};
+use futures::StreamExt;
+use health::{Health, Target};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ManagedFieldsEntry;
 use kube::{
-    api::{Api, DynamicObject, Patch, PatchParams},
+    api::{Api, DynamicObject, ListParams, Patch, PatchParams, PostParams},
     core::{GroupVersionKind, TypeMeta},
     discovery::{ApiCapabilities, ApiResource, Discovery, Scope},
-    Client,
+    Client, ResourceExt,
 };
+use lint::Rendered;
 use log::{info, trace, warn};
-use mlua::{Lua, Table};
+use mlua::{Function, Lua, Nil, Table};
+use policy::Policies;
+use report::{Action, Failure, Outcome};
+use schema::Schemas;
 
+mod applyset;
+mod client;
 pub mod config;
+mod conflicts;
+mod crd;
+mod delete;
+mod diff;
+mod health;
+mod k8s;
+mod lint;
+mod order;
+mod policy;
+mod report;
+mod retry;
+mod schema;
+mod suggest;
+mod validate;
 
-fn run_lua<'lua>(lua: &'lua Lua, args: &LuaArgs) -> Result<Table<'lua>> {
+/// Field manager used for server-side apply
+const FIELD_MANAGER: &str = "kluars";
+
+/// Document field holding a lua function used to check the object's health
+const HEALTH_FIELD: &str = "__health";
+
+/// Registry key for the table mapping documents to the file they come from
+const SOURCES_KEY: &str = "kluars.sources";
+
+/// Wraps `require` to remember which file the tables returned by modules
+/// come from, so errors can point at the source of a document
+const TRACK_SOURCES: &str = r#"
+local sources = ...
+local require = require
+function _G.require(name)
+    local module = require(name)
+    if type(module) == 'table' then
+        local file = package.searchpath(name, package.path)
+        sources[module] = sources[module] or file
+        for _, v in ipairs(module) do
+            if type(v) == 'table' then
+                sources[v] = sources[v] or file
+            end
+        end
+    end
+    return module
+end
+"#;
+
+/// Registry key for the metatable of tables created by `kluars.clusters`
+const CLUSTERS_KEY: &str = "kluars.clusters";
+
+/// Document field holding the kubeconfig context the object is applied to
+const CLUSTER_FIELD: &str = "__cluster";
+
+fn run_lua<'lua>(lua: &'lua Lua, args: &Global) -> Result<Table<'lua>> {
+    let (context, cluster) = client::target(&args.kube);
     let LuaArgs {
         path, args, values, ..
-    } = args;
-    let script = if path.is_dir() {
-        fs::read_to_string(path.join("init.lua"))?
+    } = &args.lua_args;
+    let path = if path.is_dir() {
+        path.join("init.lua")
     } else {
-        fs::read_to_string(path)?
+        path.clone()
     };
+    let script = fs::read_to_string(&path)?;
 
     let globals = lua.globals();
 
+    let sources = lua.create_table()?;
+    let weak = lua.create_table()?;
+    weak.set("__mode", "k")?;
+    sources.set_metatable(Some(weak));
+    lua.load(TRACK_SOURCES)
+        .set_name("Source tracking")
+        .call::<_, ()>(sources.clone())?;
+    lua.set_named_registry_value(SOURCES_KEY, sources)?;
+    k8s::preload(lua)?;
+
+    // Let scripts know which cluster they are targeting
+    let kluars = lua.create_table()?;
+    kluars.set("context", context)?;
+    kluars.set("cluster", cluster)?;
+
+    // Tables keyed by context are marked through their metatable
+    let marker = lua.create_table()?;
+    lua.set_named_registry_value(CLUSTERS_KEY, marker)?;
+    let clusters = lua.create_function(|lua, table: Table| {
+        table.set_metatable(Some(lua.named_registry_value(CLUSTERS_KEY)?));
+        Ok(table)
+    })?;
+    kluars.set("clusters", clusters)?;
+    globals.set("kluars", kluars)?;
+
     if let Some(values) = values {
         if values.is_file() {
             lua.load(values.clone())
@@ -37,7 +123,28 @@ fn run_lua<'lua>(lua: &'lua Lua, args: &LuaArgs) -> Result<Table<'lua>> {
         globals.set(k.clone(), v.clone())?;
     }
 
-    Ok(lua.load(&script).eval()?)
+    // Name the chunk after the file so errors point at its lines
+    Ok(lua
+        .load(&script)
+        .set_name(format!("@{}", path.display()))
+        .eval()?)
+}
+
+/// Get the file a document was rendered in, with the line when it is
+/// known, defaulting to the main script
+fn source(lua: &Lua, table: &Table, args: &LuaArgs) -> String {
+    lua.named_registry_value::<Table>(SOURCES_KEY)
+        .and_then(|sources| sources.get::<_, Option<String>>(table.clone()))
+        .ok()
+        .flatten()
+        .unwrap_or_else(|| {
+            let path = if args.path.is_dir() {
+                args.path.join("init.lua")
+            } else {
+                args.path.clone()
+            };
+            path.display().to_string()
+        })
 }
 
 fn set_env(args: &LuaArgs) {
@@ -53,77 +160,633 @@ fn translate(args: Global) -> Result<()> {
     set_env(&args.lua_args);
     let mut out = String::new();
     let lua = Lua::new();
-    let table = run_lua(&lua, &args.lua_args)?;
+    let table = run_lua(&lua, &args)?;
+    let multidoc = table.get::<_, Table>(1).is_ok() || is_multi_cluster(&lua, &table)?;
+    let clusters = clusters(&lua, table, &args.kube)?;
+    check(&lua, &clusters, &args)?;
 
-    if table.get::<_, Table>(1).is_ok() {
-        // table is an array, produce multidoc yaml
-        for pair in table.pairs::<u64, Table>() {
-            let (_, v) = pair?;
-            out += "---\n";
-            out += &serde_yaml::to_string(&v)?;
+    for cluster in clusters {
+        if let Some(context) = &cluster.context {
+            out += &format!("# context: {context}\n");
+        }
+        for doc in cluster.documents {
+            take_health(&doc)?;
+            if multidoc {
+                // produce multidoc yaml
+                out += "---\n";
+            }
+            out += &serde_yaml::to_string(&doc)?;
         }
-    } else {
-        out += &serde_yaml::to_string(&table)?;
     }
 
     println!("{out}");
     Ok(())
 }
 
+/// Convert the documents of a cluster to JSON, along with the file each
+/// of them comes from
+fn rendered(lua: &Lua, cluster: &Cluster, args: &LuaArgs) -> Result<Vec<Rendered>> {
+    cluster
+        .documents
+        .iter()
+        .map(|table| {
+            Ok(Rendered {
+                source: source(lua, table, args),
+                doc: schema::document(lua, table)?,
+            })
+        })
+        .collect()
+}
+
+/// Run the checks requested through `--validate` and `--policy` on every
+/// document, printing the problems found
+fn check(lua: &Lua, clusters: &[Cluster], args: &Global) -> Result<()> {
+    if !args.validate && args.policy.is_none() {
+        return Ok(());
+    }
+
+    let policies = match &args.policy {
+        Some(dir) => Some(Policies::load(lua, dir)?),
+        None => None,
+    };
+    let mut diagnostics = Vec::new();
+    for cluster in clusters {
+        let docs = rendered(lua, cluster, &args.lua_args)?;
+        if args.validate {
+            diagnostics.extend(lint::schema(&docs, &Schemas::default()));
+        }
+        if let Some(policies) = &policies {
+            diagnostics.extend(policies.check(lua, &docs)?);
+        }
+    }
+    lint::report(&diagnostics)
+}
+
+/// Remove the custom health check from a document, it can't be serialized
+/// and is not part of the k8s object.
+fn take_health<'lua>(table: &Table<'lua>) -> Result<Option<Function<'lua>>> {
+    let health = table.get(HEALTH_FIELD)?;
+    table.set(HEALTH_FIELD, Nil)?;
+    Ok(health)
+}
+
+/// A single k8s object rendered by a lua script
+struct Document<'lua> {
+    table: Table<'lua>,
+    gvk: GroupVersionKind,
+    name: String,
+    namespace: Option<String>,
+    /// The object has no name and relies on `metadata.generateName`
+    generated: bool,
+    health: Option<Function<'lua>>,
+}
+
+impl<'lua> Document<'lua> {
+    fn new(table: Table<'lua>, args: &Global) -> Result<Self> {
+        let health = take_health(&table)?;
+        let meta: Table = table.get("metadata")?;
+
+        let namespace = meta
+            .get::<&str, String>("namespace")
+            .ok()
+            .or(args.namespace.clone());
+        let kind: String = table.get("kind")?;
+        let api_version: String = table.get("apiVersion")?;
+        let gvk = GroupVersionKind::try_from(TypeMeta { api_version, kind })?;
+        let name: Option<String> = meta.get("name")?;
+        let generated = name.is_none();
+        let name = name.or(meta.get("generateName")?).unwrap_or_default();
+
+        Ok(Document {
+            table,
+            gvk,
+            name,
+            namespace,
+            generated,
+            health,
+        })
+    }
+
+    /// Get a dynamic API for the document, if its GVK is known to the
+    /// cluster
+    fn api(&self, client: &Client, discovery: &Discovery) -> Option<Api<DynamicObject>> {
+        let (ar, caps) = discovery.resolve_gvk(&self.gvk)?;
+        Some(dynamic_api(
+            ar,
+            caps,
+            client.clone(),
+            self.namespace.as_deref(),
+            false,
+        ))
+    }
+
+    /// Get dynamic APIs for every namespace the document applies to.
+    ///
+    /// With `--all-namespaces`, namespaced documents without an explicit
+    /// namespace match the objects with the same name in any namespace.
+    async fn apis(
+        &self,
+        args: &Global,
+        client: &Client,
+        discovery: &Discovery,
+    ) -> Result<Vec<Api<DynamicObject>>> {
+        let Some((ar, caps)) = discovery.resolve_gvk(&self.gvk) else {
+            return Ok(Vec::new());
+        };
+        if !args.all || caps.scope == Scope::Cluster || self.namespace.is_some() {
+            let ns = self.namespace.as_deref();
+            return Ok(vec![dynamic_api(ar, caps, client.clone(), ns, false)]);
+        }
+
+        let all = dynamic_api(ar.clone(), caps.clone(), client.clone(), None, true);
+        let lp = ListParams::default().fields(&format!("metadata.name={}", self.name));
+        Ok(all
+            .list(&lp)
+            .await?
+            .iter()
+            .map(|obj| {
+                let ns = obj.namespace();
+                dynamic_api(
+                    ar.clone(),
+                    caps.clone(),
+                    client.clone(),
+                    ns.as_deref(),
+                    false,
+                )
+            })
+            .collect())
+    }
+}
+
+/// Documents rendered for a single cluster
+struct Cluster<'lua> {
+    /// Context requested by the lua script, if any
+    context: Option<String>,
+    kube: KubeArgs,
+    documents: Vec<Table<'lua>>,
+}
+
+/// Whether the table returned by a lua script is keyed by kubeconfig
+/// context, e.g. `kluars.clusters { ["prod-eu"] = {...}, ["prod-us"] = {...} }`
+fn is_multi_cluster(lua: &Lua, table: &Table) -> Result<bool> {
+    let marker: Table = lua.named_registry_value(CLUSTERS_KEY)?;
+    Ok(table.get_metatable().is_some_and(|mt| mt == marker))
+}
+
+/// Remove the cluster hint from a document
+fn take_cluster(table: &Table) -> Result<Option<String>> {
+    let cluster = table.get(CLUSTER_FIELD)?;
+    table.set(CLUSTER_FIELD, Nil)?;
+    Ok(cluster)
+}
+
+/// Group the documents returned by a lua script by the cluster they target.
+/// Documents without a context use the one from the command line options.
+fn clusters<'lua>(lua: &Lua, table: Table<'lua>, args: &KubeArgs) -> Result<Vec<Cluster<'lua>>> {
+    let mut groups: Vec<(Option<String>, Table)> = Vec::new();
+    if is_multi_cluster(lua, &table)? {
+        let mut contexts = table
+            .pairs::<String, Table>()
+            .collect::<mlua::Result<Vec<_>>>()?;
+        // Lua doesn't keep the order of keys, sort them to keep the output stable
+        contexts.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (context, docs) in contexts {
+            for doc in documents(docs)? {
+                let hint = take_cluster(&doc)?;
+                groups.push((hint.or(Some(context.clone())), doc));
+            }
+        }
+    } else {
+        for doc in documents(table)? {
+            groups.push((take_cluster(&doc)?, doc));
+        }
+    }
+
+    let mut clusters: Vec<Cluster> = Vec::new();
+    for (context, doc) in groups {
+        match clusters.iter_mut().find(|c| c.context == context) {
+            Some(cluster) => cluster.documents.push(doc),
+            None => clusters.push(Cluster {
+                kube: match &context {
+                    // The cluster and user of the context chosen by the script
+                    // are used rather than the ones from the command line
+                    Some(context) => KubeArgs {
+                        context: Some(context.clone()),
+                        cluster: None,
+                        user: None,
+                        ..args.clone()
+                    },
+                    None => args.clone(),
+                },
+                context,
+                documents: vec![doc],
+            }),
+        }
+    }
+    Ok(clusters)
+}
+
+/// Split the table returned by a lua script into its documents
+fn documents(table: Table) -> Result<Vec<Table>> {
+    if table.get::<_, Table>(1).is_ok() {
+        // table is an array, each entry is a document
+        Ok(table
+            .sequence_values::<Table>()
+            .collect::<mlua::Result<_>>()?)
+    } else {
+        Ok(vec![table])
+    }
+}
+
 async fn apply_single(
-    args: &Global,
-    table: &Table<'_>,
+    doc: &Document<'_>,
+    args: &ApplyArgs,
     client: &Client,
     discovery: &Discovery,
     ssapply: &PatchParams,
-) -> Result<()> {
-    let meta: Table = table.get("metadata")?;
+    applyset: Option<&ApplySet>,
+) -> Result<Option<(DynamicObject, Action)>> {
+    let Document {
+        table, gvk, name, ..
+    } = doc;
 
-    let namespace = meta
-        .get::<&str, String>("namespace")
-        .ok()
-        .or(args.namespace.clone());
-    let kind: String = table.get("kind")?;
-    let api_version: String = table.get("apiVersion")?;
-    let gvk = GroupVersionKind::try_from(TypeMeta { api_version, kind })?;
-    let name: String = meta
-        .get("name")
-        .or_else(|_| meta.get("generateName"))
-        .unwrap_or_default();
-
-    if let Some((ar, caps)) = discovery.resolve_gvk(&gvk) {
-        let api = dynamic_api(ar, caps, client.clone(), namespace.as_deref(), false);
-        trace!(
-            "Applying {}: \n{}",
+    let Some((ar, caps)) = discovery.resolve_gvk(gvk) else {
+        warn!("Cannot apply document for unknown {:?}", gvk);
+        return Ok(None);
+    };
+
+    trace!(
+        "Applying {}: \n{}",
+        gvk.kind,
+        serde_yaml::to_string(&table)?
+    );
+    let mut data: serde_json::Value = serde_json::to_value(table)?;
+    if let Some(applyset) = applyset {
+        applyset.label(&mut data);
+    }
+
+    if args.global.all && caps.scope == Scope::Namespaced && doc.namespace.is_none() {
+        anyhow::bail!(
+            "{} {} needs an explicit namespace when using --all-namespaces",
             gvk.kind,
-            serde_yaml::to_string(&table)?
+            name
         );
-        let data: serde_json::Value = serde_json::to_value(table)?;
-        let _r = api.patch(&name, ssapply, &Patch::Apply(data)).await?;
-        info!("applied {} {}", gvk.kind, name);
+    }
+
+    if args.dry_run == Some(DryRun::Client) {
+        let mut obj: DynamicObject = serde_json::from_value(data)?;
+        if caps.scope == Scope::Cluster {
+            if let Some(ns) = &obj.metadata.namespace {
+                anyhow::bail!(
+                    "{} {} is cluster scoped but has namespace {}",
+                    gvk.kind,
+                    name,
+                    ns
+                );
+            }
+        } else if obj.metadata.namespace.is_none() {
+            let ns = doc
+                .namespace
+                .as_deref()
+                .unwrap_or(client.default_namespace());
+            obj.metadata.namespace = Some(ns.to_string());
+        }
+
+        // Without the server merging the document there's no telling
+        // whether an existing object would change
+        let api = dynamic_api(ar, caps, client.clone(), doc.namespace.as_deref(), false);
+        let action = if doc.generated || api.get_opt(name).await?.is_none() {
+            Action::Created
+        } else {
+            Action::Configured
+        };
+        return Ok(Some((obj, action)));
+    }
+
+    let api = dynamic_api(ar, caps, client.clone(), doc.namespace.as_deref(), false);
+    let r = if doc.generated {
+        (create(doc, &api, data, ssapply).await?, Action::Created)
     } else {
-        warn!("Cannot apply document for unknown {:?}", gvk);
+        let before = api.get_opt(name).await?;
+        match api.patch(name, ssapply, &Patch::Apply(data)).await {
+            Ok(r) => {
+                let action = match &before {
+                    None => Action::Created,
+                    Some(before) if unchanged(before, &r) => Action::Unchanged,
+                    Some(_) => Action::Configured,
+                };
+                info!("{action} {} {}", gvk.kind, name);
+                (r, action)
+            }
+            Err(kube::Error::Api(e)) if e.code == 409 && !ssapply.force => {
+                anyhow::bail!(conflicts::report(&gvk.kind, name, &e.message));
+            }
+            Err(e) => return Err(e.into()),
+        }
+    };
+    Ok(Some(r))
+}
+
+/// Managed fields of an object, without the timestamps that change on every
+/// apply
+fn managed_fields(obj: &DynamicObject) -> Vec<ManagedFieldsEntry> {
+    obj.managed_fields()
+        .iter()
+        .cloned()
+        .map(|entry| ManagedFieldsEntry {
+            time: None,
+            ..entry
+        })
+        .collect()
+}
+
+/// Whether applying an object left it as it was. Dry runs never bump the
+/// resourceVersion, so the content is compared as well.
+fn unchanged(before: &DynamicObject, after: &DynamicObject) -> bool {
+    before.resource_version() == after.resource_version()
+        && before.data == after.data
+        && before.labels() == after.labels()
+        && before.annotations() == after.annotations()
+        && managed_fields(before) == managed_fields(after)
+}
+
+/// Create an object that relies on `metadata.generateName`, server-side
+/// apply needs a name so these go through a regular POST instead.
+///
+/// The name assigned by the server is written back into the document's
+/// metadata, where lua code running after the apply, like `__health`
+/// checks, can read it. Every document is rendered before applying, so
+/// other documents can't refer to the generated name.
+async fn create(
+    doc: &Document<'_>,
+    api: &Api<DynamicObject>,
+    data: serde_json::Value,
+    ssapply: &PatchParams,
+) -> Result<DynamicObject> {
+    let pp = PostParams {
+        dry_run: ssapply.dry_run,
+        field_manager: ssapply.field_manager.clone(),
+    };
+    let obj: DynamicObject = serde_json::from_value(data)?;
+    let r = match api.create(&pp, &obj).await {
+        Ok(r) => r,
+        Err(e) if retry::throttled(&e) => return Err(e.into()),
+        // The object may have been created even though the request failed,
+        // retrying would create a duplicate under another name
+        Err(e) => return Err(retry::Permanent(e).into()),
+    };
+
+    if !pp.dry_run {
+        let meta: Table = doc.table.get("metadata")?;
+        meta.set("name", r.name_any())?;
+    }
+    info!("created {} {}", doc.gvk.kind, r.name_any());
+    Ok(r)
+}
+
+async fn apply(args: ApplyArgs) -> Result<()> {
+    set_env(&args.global.lua_args);
+    let lua = Lua::new();
+    let table = run_lua(&lua, &args.global)?;
+    let clusters = clusters(&lua, table, &args.global.kube)?;
+    check(&lua, &clusters, &args.global)?;
+    let mut outcomes: Vec<Vec<Outcome>> = clusters.iter().map(|_| Vec::new()).collect();
+
+    if let [cluster] = &clusters[..] {
+        let result = apply_cluster(&args, &lua, cluster, &mut outcomes[0]).await;
+        if let Some(output) = args.output {
+            report::print(&outcomes[0], output)?;
+        }
+        return result;
+    }
+
+    // Clusters are independent from each other, apply to all of them at
+    // the same time
+    let results = futures::future::join_all(
+        clusters
+            .iter()
+            .zip(outcomes.iter_mut())
+            .map(|(c, outcomes)| apply_cluster(&args, &lua, c, outcomes)),
+    )
+    .await;
+
+    let mut failed = 0;
+    for (cluster, result) in clusters.iter().zip(results) {
+        let context = cluster.kube.context.as_deref().unwrap_or("default");
+        let status = match result {
+            Ok(()) => "applied".to_string(),
+            Err(e) => {
+                failed += 1;
+                format!("failed: {e:#}")
+            }
+        };
+        // Keep stdout for the report when one is requested
+        if args.output.is_some() {
+            eprintln!("{context}: {status}");
+        } else {
+            println!("{context}: {status}");
+        }
+    }
+    if let Some(output) = args.output {
+        let outcomes: Vec<Outcome> = outcomes.into_iter().flatten().collect();
+        report::print(&outcomes, output)?;
+    }
+    if failed > 0 {
+        anyhow::bail!("apply failed on {failed} clusters");
     }
     Ok(())
 }
 
-async fn apply(args: Global) -> Result<()> {
-    let client = Client::try_default().await?;
-    let discovery = Discovery::new(client.clone()).run().await?;
-    let ssapply = PatchParams::apply("kubectl-light").force();
+/// Apply the documents targeting a single cluster, recording the result
+/// for every object in `outcomes`
+async fn apply_cluster(
+    args: &ApplyArgs,
+    lua: &Lua,
+    cluster: &Cluster<'_>,
+    outcomes: &mut Vec<Outcome>,
+) -> Result<()> {
+    let context = &cluster.kube.context;
+    let client = client::client(&cluster.kube).await?;
+    let mut discovery = Discovery::new(client.clone()).run().await?;
+    let mut ssapply = args.server_side.params();
+    if args.dry_run == Some(DryRun::Server) {
+        ssapply = ssapply.dry_run();
+    }
 
-    set_env(&args.lua_args);
-    let lua = Lua::new();
-    let table = run_lua(&lua, &args.lua_args)?;
+    let docs = cluster
+        .documents
+        .iter()
+        .map(|t| Document::new(t.clone(), &args.global))
+        .collect::<Result<Vec<_>>>()?;
+    let tiers = order::sort(docs)?;
+    let docs: Vec<&Document> = tiers.iter().flatten().collect();
 
-    if table.get::<_, Table>(1).is_ok() {
-        // table is an array, iterate over entries
-        for pair in table.pairs::<u64, Table>() {
-            let (_, v) = pair?;
-            apply_single(&args, &v, &client, &discovery, &ssapply).await?;
+    let applyset = match &args.applyset {
+        Some(name) => {
+            let ns = args.global.namespace.as_deref();
+            Some(ApplySet::new(&client, name, ns, &args.server_side.field_manager).await?)
         }
-    } else {
-        apply_single(&args, &table, &client, &discovery, &ssapply).await?;
+        None => None,
+    };
+
+    if let (Some(applyset), None) = (&applyset, args.dry_run) {
+        let members = docs
+            .iter()
+            .map(|doc| Member {
+                group_kind: group_kind(&doc.gvk),
+                namespace: doc.namespace.clone(),
+                name: doc.name.clone(),
+            })
+            .collect();
+        applyset.begin(&members).await?;
+    }
+
+    let mut members = HashSet::new();
+    let mut targets = Vec::new();
+    let mut crds = Vec::new();
+    let mut unresolved = Vec::new();
+    let mut retried = Vec::new();
+    let mut failures = Vec::new();
+    for tier in &tiers {
+        // Custom resources may be defined by CRDs applied in this same run,
+        // those need to be established before discovery can find them
+        let unknown = tier
+            .iter()
+            .any(|doc| discovery.resolve_gvk(&doc.gvk).is_none());
+        if unknown && !crds.is_empty() {
+            crd::wait_established(&client, &crds, args.timeout).await?;
+            discovery = Discovery::new(client.clone()).run().await?;
+            crds.clear();
+        }
+
+        // Documents in a tier are independent, apply them concurrently
+        // while still handling the results in order
+        let mut results = futures::stream::iter(tier)
+            .map(|doc| async {
+                let start = Instant::now();
+                let result = retry::retry(args.retries, args.retry_backoff, || {
+                    apply_single(doc, args, &client, &discovery, &ssapply, applyset.as_ref())
+                })
+                .await;
+                (result, start.elapsed())
+            })
+            .buffered(args.concurrency.get());
+
+        for doc in tier {
+            let ((applied, attempts), elapsed) =
+                results.next().await.expect("missing apply result");
+            if attempts > 0 {
+                retried.push((doc.gvk.kind.clone(), doc.name.clone(), attempts));
+            }
+            let applied = match applied {
+                Ok(applied) => applied,
+                Err(error) => {
+                    let failure = Failure {
+                        api_version: doc.table.get("apiVersion")?,
+                        kind: doc.gvk.kind.clone(),
+                        namespace: doc.namespace.clone(),
+                        name: doc.name.clone(),
+                        source: source(lua, &doc.table, &args.global.lua_args),
+                        error,
+                    };
+                    outcomes.push(failure.outcome(context.clone(), elapsed));
+                    if !args.continue_on_error {
+                        return Err(failure.error);
+                    }
+                    failures.push(failure);
+                    continue;
+                }
+            };
+            let Some((obj, action)) = applied else {
+                unresolved.push((doc.gvk.clone(), doc.name.clone()));
+                continue;
+            };
+            outcomes.push(Outcome::new(context.clone(), &obj, action, Some(elapsed)));
+
+            if args.output.is_none() {
+                // Same format as kubectl, e.g. `deployment.apps/nginx configured`
+                let prefix = match &cluster.context {
+                    Some(context) => format!("{context}: "),
+                    None => String::new(),
+                };
+                let suffix = match args.dry_run {
+                    Some(DryRun::Client) => " (client dry run)",
+                    Some(DryRun::Server) => " (server dry run)",
+                    None => "",
+                };
+                let gk = group_kind(&doc.gvk).to_lowercase();
+                println!("{prefix}{gk}/{} {action}{suffix}", obj.name_any());
+            }
+            if doc.gvk.kind == "CustomResourceDefinition" && args.dry_run.is_none() {
+                crds.push(obj.name_any());
+            }
+            if let (Some(api), true) = (doc.api(&client, &discovery), args.wait) {
+                targets.push(Target {
+                    api,
+                    kind: doc.gvk.kind.clone(),
+                    name: obj.name_any(),
+                    check: doc.health.clone(),
+                });
+            }
+            members.insert(Member {
+                group_kind: group_kind(&doc.gvk),
+                namespace: obj.namespace(),
+                name: obj.name_any(),
+            });
+        }
+    }
+
+    if !retried.is_empty() {
+        eprintln!("Documents that needed retries:");
+        for (kind, name, attempts) in &retried {
+            eprintln!("  {kind} {name}: {attempts} retries");
+        }
+    }
+
+    if !unresolved.is_empty() {
+        suggest::report(&discovery, &unresolved);
+        if args.strict {
+            anyhow::bail!("{} documents have unknown resource types", unresolved.len());
+        }
+    }
+
+    if let (Some(applyset), true) = (&applyset, args.prune) {
+        if failures.is_empty() {
+            let dry_run = args.dry_run.is_some();
+            for obj in applyset
+                .prune(&client, &discovery, &members, dry_run)
+                .await?
+            {
+                if dry_run && args.output.is_none() {
+                    println!("{} pruned (dry run)", applyset::path(&obj));
+                }
+                outcomes.push(Outcome::new(context.clone(), &obj, Action::Pruned, None));
+            }
+        } else {
+            // Objects that failed to apply would be pruned otherwise
+            warn!("Skipping prune, some documents failed to apply");
+        }
+    }
+
+    if args.wait && args.dry_run.is_none() {
+        let statuses = health::wait(lua, &targets, args.timeout).await?;
+        if args.output.is_some() {
+            health::report(&targets, &statuses, &mut io::stderr())?;
+        } else {
+            health::report(&targets, &statuses, &mut io::stdout())?;
+        }
+        let unhealthy = statuses
+            .iter()
+            .filter(|s| s.health != Health::Healthy)
+            .count();
+        if unhealthy > 0 {
+            anyhow::bail!("{unhealthy} objects did not become ready");
+        }
+    }
+
+    if !failures.is_empty() {
+        report::failures(&failures);
+        anyhow::bail!("{} documents failed to apply", failures.len());
     }
     Ok(())
 }
@@ -148,5 +811,8 @@ pub async fn run(cli: Cli) -> Result<()> {
     match cli.command {
         config::Commands::Xlate(args) => translate(args),
         config::Commands::Apply(args) => apply(args).await,
+        config::Commands::Diff(args) => diff::diff(args).await,
+        config::Commands::Delete(args) => delete::delete(args).await,
+        config::Commands::Validate(args) => validate::validate(args).await,
     }
 }
@@ -0,0 +1,401 @@
+//! Structural checks of rendered documents that don't need a cluster,
+//! reported as compiler-style diagnostics
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use anyhow::Result;
+use serde_json::Value;
+
+use crate::schema::Schemas;
+
+/// Kinds with a pod template and a selector that needs to match it
+const WORKLOADS: &[&str] = &["Deployment", "StatefulSet", "DaemonSet", "ReplicaSet"];
+
+/// Kinds whose names need to be DNS-1123 labels rather than subdomains
+const LABEL_NAMES: &[&str] = &["Namespace"];
+
+/// Kinds whose names need to be DNS-1035 labels, which can't start with a
+/// digit
+const DNS_1035_NAMES: &[&str] = &["Service"];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A problem found in a document
+pub(crate) struct Diagnostic {
+    pub severity: Severity,
+    /// Lua file the document was rendered in, along with the line for
+    /// documents built with the `k8s` module
+    pub source: String,
+    /// The object the problem was found in, e.g. `apps/v1 Deployment nginx`
+    pub object: String,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}: {}: {}",
+            self.source, self.severity, self.object, self.message
+        )
+    }
+}
+
+/// A document rendered by lua, converted to JSON
+pub(crate) struct Rendered {
+    pub source: String,
+    pub doc: Value,
+}
+
+impl Rendered {
+    fn str(&self, pointer: &str) -> Option<&str> {
+        self.doc.pointer(pointer).and_then(Value::as_str)
+    }
+
+    fn kind(&self) -> &str {
+        self.str("/kind").unwrap_or_default()
+    }
+
+    fn name(&self) -> &str {
+        self.str("/metadata/name")
+            .or(self.str("/metadata/generateName"))
+            .unwrap_or_default()
+    }
+
+    fn object(&self) -> String {
+        let api_version = self.str("/apiVersion").unwrap_or_default();
+        match self.name() {
+            "" => format!("{api_version} {}", self.kind()),
+            name => format!("{api_version} {} {name}", self.kind()),
+        }
+    }
+
+    pub fn diagnostic(&self, severity: Severity, message: String) -> Diagnostic {
+        Diagnostic {
+            severity,
+            source: self.source.clone(),
+            object: self.object(),
+            message,
+        }
+    }
+}
+
+/// Check documents against the schemas of their types
+pub(crate) fn schema(docs: &[Rendered], schemas: &Schemas) -> Vec<Diagnostic> {
+    docs.iter()
+        .flat_map(|r| {
+            let issues = schemas.validate(&r.doc).unwrap_or_default();
+            issues
+                .into_iter()
+                .map(|issue| r.diagnostic(Severity::Error, issue.to_string()))
+        })
+        .collect()
+}
+
+fn alphanumeric(c: Option<char>) -> bool {
+    c.is_some_and(|c| c.is_ascii_alphanumeric())
+}
+
+/// Check a DNS-1123 label, as used for the names of namespaces
+fn dns_label(s: &str) -> Option<String> {
+    if s.is_empty() || s.len() > 63 {
+        return Some(format!("`{s}` must have between 1 and 63 characters"));
+    }
+    let valid = s
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && alphanumeric(s.chars().next())
+        && alphanumeric(s.chars().last());
+    if !valid {
+        return Some(format!(
+            "`{s}` must consist of lower case alphanumeric characters or '-', \
+             and start and end with an alphanumeric character"
+        ));
+    }
+    None
+}
+
+/// Check a DNS-1035 label, as used for the names of services
+fn dns_1035_label(s: &str) -> Option<String> {
+    if let Some(error) = dns_label(s) {
+        return Some(error);
+    }
+    if !s.starts_with(|c: char| c.is_ascii_lowercase()) {
+        return Some(format!("`{s}` must start with a lower case letter"));
+    }
+    None
+}
+
+/// Check a DNS-1123 subdomain, as used for the names of most objects
+fn dns_subdomain(s: &str) -> Option<String> {
+    if s.len() > 253 {
+        return Some(format!("`{s}` must have at most 253 characters"));
+    }
+    let valid = s.split('.').all(|part| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+            && alphanumeric(part.chars().next())
+            && alphanumeric(part.chars().last())
+    });
+    if !valid {
+        return Some(format!(
+            "`{s}` must consist of lower case alphanumeric characters, '-' or '.', \
+             and start and end with an alphanumeric character"
+        ));
+    }
+    None
+}
+
+/// Check the value of a label, or the name part of its key
+fn label_value(s: &str) -> Option<String> {
+    if s.len() > 63 {
+        return Some(format!("`{s}` must have at most 63 characters"));
+    }
+    let valid = s.is_empty()
+        || (s
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+            && alphanumeric(s.chars().next())
+            && alphanumeric(s.chars().last()));
+    if !valid {
+        return Some(format!(
+            "`{s}` must consist of alphanumeric characters, '-', '_' or '.', \
+             and start and end with an alphanumeric character"
+        ));
+    }
+    None
+}
+
+/// Check a label key, an optional DNS subdomain prefix and a name
+fn label_key(s: &str) -> Option<String> {
+    let (prefix, name) = match s.split_once('/') {
+        Some((prefix, name)) => (Some(prefix), name),
+        None => (None, s),
+    };
+    if name.is_empty() {
+        return Some(format!("`{s}` must have a name"));
+    }
+    prefix.and_then(dns_subdomain).or_else(|| label_value(name))
+}
+
+fn labels<'a>(doc: &'a Value, pointer: &str) -> BTreeMap<&'a str, &'a str> {
+    doc.pointer(pointer)
+        .and_then(Value::as_object)
+        .map(|labels| {
+            labels
+                .iter()
+                .filter_map(|(k, v)| Some((k.as_str(), v.as_str()?)))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Whether every label in `selector` is set in `labels`
+fn selects(selector: &BTreeMap<&str, &str>, labels: &BTreeMap<&str, &str>) -> bool {
+    selector.iter().all(|(k, v)| labels.get(k) == Some(v))
+}
+
+fn required(r: &Rendered, diagnostics: &mut Vec<Diagnostic>) -> bool {
+    let mut missing = Vec::new();
+    if r.str("/apiVersion").is_none() {
+        missing.push("apiVersion");
+    }
+    if r.str("/kind").is_none() {
+        missing.push("kind");
+    }
+    if r.str("/metadata/name").is_none() && r.str("/metadata/generateName").is_none() {
+        missing.push("metadata.name");
+    }
+    for field in &missing {
+        diagnostics.push(r.diagnostic(Severity::Error, format!("missing required field {field}")));
+    }
+    missing.is_empty()
+}
+
+fn names(r: &Rendered, diagnostics: &mut Vec<Diagnostic>) {
+    if let Some(name) = r.str("/metadata/name") {
+        let error = if LABEL_NAMES.contains(&r.kind()) {
+            dns_label(name)
+        } else if DNS_1035_NAMES.contains(&r.kind()) {
+            dns_1035_label(name)
+        } else {
+            dns_subdomain(name)
+        };
+        if let Some(error) = error {
+            diagnostics.push(r.diagnostic(Severity::Error, format!("metadata.name: {error}")));
+        }
+    }
+    if let Some(error) = r.str("/metadata/namespace").and_then(dns_label) {
+        diagnostics.push(r.diagnostic(Severity::Error, format!("metadata.namespace: {error}")));
+    }
+
+    for (pointer, path) in [
+        ("/metadata/labels", "metadata.labels"),
+        (
+            "/spec/template/metadata/labels",
+            "spec.template.metadata.labels",
+        ),
+    ] {
+        for (key, value) in labels(&r.doc, pointer) {
+            if let Some(error) = label_key(key).or_else(|| label_value(value)) {
+                let message = format!("{path}.{key}: {error}");
+                diagnostics.push(r.diagnostic(Severity::Error, message));
+            }
+        }
+    }
+}
+
+/// Pods rendered directly or through the template of a workload
+struct Pods<'a> {
+    namespace: Option<&'a str>,
+    labels: BTreeMap<&'a str, &'a str>,
+    spec: Option<&'a Value>,
+}
+
+/// Check every document on its own and against each other, documents are
+/// expected to target the same cluster
+pub(crate) fn lint(docs: &[Rendered], namespace: Option<&str>) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut seen: HashMap<(String, &str, Option<&str>, &str), &Rendered> = HashMap::new();
+    let mut pods = Vec::new();
+
+    for r in docs {
+        if !required(r, &mut diagnostics) {
+            continue;
+        }
+        names(r, &mut diagnostics);
+
+        let group = r
+            .str("/apiVersion")
+            .and_then(|v| v.rsplit_once('/'))
+            .map_or("", |(group, _)| group);
+        let ns = r.str("/metadata/namespace").or(namespace);
+        if let Some(name) = r.str("/metadata/name") {
+            let identity = (group.to_string(), r.kind(), ns, name);
+            if let Some(first) = seen.insert(identity, r) {
+                let message = format!("duplicate object, first defined in {}", first.source);
+                diagnostics.push(r.diagnostic(Severity::Error, message));
+            }
+        }
+
+        if r.kind() == "Pod" {
+            pods.push(Pods {
+                namespace: ns,
+                labels: labels(&r.doc, "/metadata/labels"),
+                spec: r.doc.get("spec"),
+            });
+        } else if WORKLOADS.contains(&r.kind()) {
+            let template = labels(&r.doc, "/spec/template/metadata/labels");
+            let selector = labels(&r.doc, "/spec/selector/matchLabels");
+            if !selects(&selector, &template) {
+                let message = "spec.selector does not match spec.template.metadata.labels";
+                diagnostics.push(r.diagnostic(Severity::Error, message.to_string()));
+            }
+            pods.push(Pods {
+                namespace: ns,
+                labels: template,
+                spec: r.doc.pointer("/spec/template/spec"),
+            });
+        }
+    }
+
+    for r in docs.iter().filter(|r| r.kind() == "Service") {
+        services(
+            r,
+            r.str("/metadata/namespace").or(namespace),
+            &pods,
+            &mut diagnostics,
+        );
+    }
+    diagnostics
+}
+
+/// Check that services select rendered pods exposing the ports they target
+fn services(
+    r: &Rendered,
+    namespace: Option<&str>,
+    pods: &[Pods],
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let selector = labels(&r.doc, "/spec/selector");
+    if selector.is_empty() {
+        return;
+    }
+    let selected: Vec<&Pods> = pods
+        .iter()
+        .filter(|p| p.namespace == namespace && selects(&selector, &p.labels))
+        .collect();
+    if selected.is_empty() {
+        let message = "spec.selector matches no pods in the rendered documents";
+        diagnostics.push(r.diagnostic(Severity::Warning, message.to_string()));
+        return;
+    }
+
+    let container_ports: Vec<&Value> = selected
+        .iter()
+        .filter_map(|p| p.spec?.get("containers")?.as_array())
+        .flatten()
+        .filter_map(|c| c.get("ports")?.as_array())
+        .flatten()
+        .collect();
+
+    let ports = r.doc.pointer("/spec/ports").and_then(Value::as_array);
+    for (i, port) in ports.into_iter().flatten().enumerate() {
+        let Some(target) = port.get("targetPort").or(port.get("port")) else {
+            continue;
+        };
+        if let Some(name) = target.as_str() {
+            if !container_ports
+                .iter()
+                .any(|p| p.get("name") == Some(target))
+            {
+                let message = format!("spec.ports[{i}]: no selected pod has a port named {name}");
+                diagnostics.push(r.diagnostic(Severity::Error, message));
+            }
+        } else if !container_ports
+            .iter()
+            .any(|p| p.get("containerPort") == Some(target))
+        {
+            let message = format!("spec.ports[{i}]: no selected pod exposes port {target}");
+            diagnostics.push(r.diagnostic(Severity::Warning, message));
+        }
+    }
+}
+
+/// Print diagnostics to stderr, failing if any of them is an error
+pub(crate) fn report(diagnostics: &[Diagnostic]) -> Result<()> {
+    for diagnostic in diagnostics {
+        eprintln!("{diagnostic}");
+    }
+
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Error)
+        .count();
+    let warnings = diagnostics.len() - errors;
+    if errors > 0 {
+        anyhow::bail!("validation failed: {errors} errors, {warnings} warnings");
+    }
+    if warnings > 0 {
+        eprintln!("{warnings} warnings");
+    }
+    Ok(())
+}
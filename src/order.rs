@@ -0,0 +1,315 @@
+//! Ordering of documents based on the dependencies between kinds
+
+use std::collections::BTreeSet;
+
+use anyhow::{bail, Result};
+use mlua::Table;
+
+use crate::Document;
+
+/// Annotation used to explicitly order documents after others
+const DEPENDS_ON_ANNOTATION: &str = "kluars.io/depends-on";
+
+/// Kinds grouped in the order they need to be created, kinds not listed
+/// here, like custom resources, are handled along with workloads.
+const CLASSES: &[&[&str]] = &[
+    &["Namespace"],
+    &["CustomResourceDefinition"],
+    &[
+        "ServiceAccount",
+        "ClusterRole",
+        "ClusterRoleBinding",
+        "Role",
+        "RoleBinding",
+    ],
+    &[
+        "ConfigMap",
+        "Secret",
+        "StorageClass",
+        "PersistentVolume",
+        "PersistentVolumeClaim",
+        "LimitRange",
+        "ResourceQuota",
+        "PriorityClass",
+        "NetworkPolicy",
+    ],
+    &["Service"],
+    &[],
+    &[
+        "MutatingWebhookConfiguration",
+        "ValidatingWebhookConfiguration",
+        "APIService",
+    ],
+];
+
+/// Class used for workloads and any kind not in `CLASSES`
+const WORKLOADS: usize = 5;
+
+/// Get the class a kind belongs to, lower classes need to be created first
+pub(crate) fn rank(kind: &str) -> usize {
+    CLASSES
+        .iter()
+        .position(|class| class.contains(&kind))
+        .unwrap_or(WORKLOADS)
+}
+
+/// Get the objects a document depends on from its annotation, references
+/// are comma separated and have the form `Kind/name` or
+/// `Kind/namespace/name`.
+fn depends_on(doc: &Document) -> Result<Vec<String>> {
+    let meta: Table = doc.table.get("metadata")?;
+    let Some(annotations) = meta.get::<_, Option<Table>>("annotations")? else {
+        return Ok(Vec::new());
+    };
+    let deps: Option<String> = annotations.get(DEPENDS_ON_ANNOTATION)?;
+
+    Ok(deps
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+/// Namespace set in the metadata of a document, cluster scoped objects
+/// don't have one
+fn explicit_namespace(doc: &Document) -> Option<String> {
+    let meta: Table = doc.table.get("metadata").ok()?;
+    meta.get("namespace").ok()
+}
+
+/// Whether a reference from `from` points to `doc`. References without a
+/// namespace match targets in the namespace of `from` or without an
+/// explicit namespace, like cluster scoped objects.
+fn matches(reference: &str, from: &Document, doc: &Document) -> bool {
+    let parts: Vec<&str> = reference.split('/').collect();
+    let (kind, name, namespace_matches) = match parts[..] {
+        [kind, name] => (
+            kind,
+            name,
+            explicit_namespace(doc).is_none() || doc.namespace == from.namespace,
+        ),
+        [kind, namespace, name] => (kind, name, doc.namespace.as_deref() == Some(namespace)),
+        _ => return false,
+    };
+    doc.gvk.kind == kind && doc.name == name && namespace_matches
+}
+
+/// Sort documents so the ones in lower classes come first, while making
+/// sure every document comes after the ones it explicitly depends on.
+/// Documents of the same class keep the order they were rendered in.
+///
+/// The sorted documents are split in tiers, documents in the same tier
+/// don't depend on each other and can be handled at the same time.
+pub(crate) fn sort(docs: Vec<Document>) -> Result<Vec<Vec<Document>>> {
+    // edges[i] holds the documents that depend on document i
+    let mut edges = vec![Vec::new(); docs.len()];
+    let mut pending = vec![0; docs.len()];
+
+    for (i, doc) in docs.iter().enumerate() {
+        for reference in depends_on(doc)? {
+            let Some(dep) = docs.iter().position(|d| matches(&reference, doc, d)) else {
+                bail!(
+                    "{} {} depends on {reference}, which is not rendered",
+                    doc.gvk.kind,
+                    doc.name
+                );
+            };
+            edges[dep].push(i);
+            pending[i] += 1;
+        }
+    }
+
+    let mut ready: BTreeSet<(usize, usize)> = docs
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| pending[*i] == 0)
+        .map(|(i, doc)| (rank(&doc.gvk.kind), i))
+        .collect();
+    let mut order = Vec::with_capacity(docs.len());
+
+    while let Some((_, i)) = ready.pop_first() {
+        order.push(i);
+        for &next in &edges[i] {
+            pending[next] -= 1;
+            if pending[next] == 0 {
+                ready.insert((rank(&docs[next].gvk.kind), next));
+            }
+        }
+    }
+
+    if order.len() != docs.len() {
+        bail!("Dependency cycle found between documents");
+    }
+
+    let mut tiers: Vec<Vec<usize>> = Vec::new();
+    for i in order {
+        let same_tier = tiers.last().is_some_and(|tier| {
+            rank(&docs[tier[0]].gvk.kind) == rank(&docs[i].gvk.kind)
+                && !tier.iter().any(|dep| edges[*dep].contains(&i))
+        });
+        match tiers.last_mut() {
+            Some(tier) if same_tier => tier.push(i),
+            _ => tiers.push(vec![i]),
+        }
+    }
+
+    let mut docs: Vec<Option<Document>> = docs.into_iter().map(Some).collect();
+    Ok(tiers
+        .into_iter()
+        .map(|tier| tier.into_iter().filter_map(|i| docs[i].take()).collect())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use mlua::Lua;
+
+    use super::*;
+    use crate::config::{Cli, Commands};
+
+    fn docs<'lua>(lua: &'lua Lua, script: &str) -> Vec<Document<'lua>> {
+        let Commands::Xlate(global) = Cli::parse_from(["kluars", "xlate", "test.lua"]).command
+        else {
+            unreachable!();
+        };
+        let tables: Vec<Table> = lua.load(script).eval().unwrap();
+        tables
+            .into_iter()
+            .map(|t| Document::new(t, &global).unwrap())
+            .collect()
+    }
+
+    fn names(tiers: &[Vec<Document>]) -> Vec<Vec<String>> {
+        tiers
+            .iter()
+            .map(|tier| tier.iter().map(|d| d.name.clone()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn tiers_by_class() {
+        let lua = Lua::new();
+        let docs = docs(
+            &lua,
+            r#"return {
+                { apiVersion = 'apps/v1', kind = 'Deployment', metadata = { name = 'web' } },
+                { apiVersion = 'v1', kind = 'ConfigMap', metadata = { name = 'a' } },
+                { apiVersion = 'v1', kind = 'Namespace', metadata = { name = 'app' } },
+                { apiVersion = 'v1', kind = 'ConfigMap', metadata = { name = 'b' } },
+            }"#,
+        );
+        let tiers = sort(docs).unwrap();
+        assert_eq!(names(&tiers), [vec!["app"], vec!["a", "b"], vec!["web"]]);
+    }
+
+    #[test]
+    fn dependency_splits_tier() {
+        let lua = Lua::new();
+        let docs = docs(
+            &lua,
+            r#"return {
+                {
+                    apiVersion = 'v1',
+                    kind = 'ConfigMap',
+                    metadata = {
+                        name = 'a',
+                        annotations = { ['kluars.io/depends-on'] = 'Secret/b' },
+                    },
+                },
+                { apiVersion = 'v1', kind = 'Secret', metadata = { name = 'b' } },
+            }"#,
+        );
+        let tiers = sort(docs).unwrap();
+        assert_eq!(names(&tiers), [vec!["b"], vec!["a"]]);
+    }
+
+    #[test]
+    fn cluster_scoped_dependency() {
+        let lua = Lua::new();
+        let docs = docs(
+            &lua,
+            r#"return {
+                {
+                    apiVersion = 'v1',
+                    kind = 'ConfigMap',
+                    metadata = {
+                        name = 'config',
+                        namespace = 'app',
+                        annotations = {
+                            ['kluars.io/depends-on'] = 'Namespace/app, ClusterRole/reader',
+                        },
+                    },
+                },
+                {
+                    apiVersion = 'rbac.authorization.k8s.io/v1',
+                    kind = 'ClusterRole',
+                    metadata = { name = 'reader' },
+                },
+                { apiVersion = 'v1', kind = 'Namespace', metadata = { name = 'app' } },
+            }"#,
+        );
+        let tiers = sort(docs).unwrap();
+        assert_eq!(names(&tiers), [vec!["app"], vec!["reader"], vec!["config"]]);
+    }
+
+    #[test]
+    fn cycle() {
+        let lua = Lua::new();
+        let docs = docs(
+            &lua,
+            r#"return {
+                {
+                    apiVersion = 'v1',
+                    kind = 'ConfigMap',
+                    metadata = {
+                        name = 'a',
+                        annotations = { ['kluars.io/depends-on'] = 'ConfigMap/b' },
+                    },
+                },
+                {
+                    apiVersion = 'v1',
+                    kind = 'ConfigMap',
+                    metadata = {
+                        name = 'b',
+                        annotations = { ['kluars.io/depends-on'] = 'ConfigMap/a' },
+                    },
+                },
+            }"#,
+        );
+        let Err(err) = sort(docs) else {
+            panic!("cycle not detected");
+        };
+        assert!(err.to_string().contains("cycle"));
+    }
+
+    #[test]
+    fn missing_dependency() {
+        let lua = Lua::new();
+        let docs = docs(
+            &lua,
+            r#"return {
+                {
+                    apiVersion = 'v1',
+                    kind = 'ConfigMap',
+                    metadata = {
+                        name = 'a',
+                        namespace = 'app',
+                        annotations = { ['kluars.io/depends-on'] = 'ConfigMap/other/b' },
+                    },
+                },
+                {
+                    apiVersion = 'v1',
+                    kind = 'ConfigMap',
+                    metadata = { name = 'b', namespace = 'app' },
+                },
+            }"#,
+        );
+        let Err(err) = sort(docs) else {
+            panic!("missing dependency not detected");
+        };
+        assert!(err.to_string().contains("not rendered"));
+    }
+}
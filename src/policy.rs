@@ -0,0 +1,135 @@
+//! Policy rules written in lua, checked against every rendered document
+//!
+//! Every lua file in the policy directory returns a table of rules keyed by
+//! name. A rule is either a function or a table with a `check` function and
+//! a `severity`, `deny` by default. Rules receive a copy of each document and
+//! return nothing when it complies, or one or more violations, each being a
+//! message or a table with a `message` and an optional `severity`.
+
+use std::{fs, path::Path};
+
+use anyhow::{bail, Result};
+use mlua::{Function, Lua, LuaSerdeExt, Table, Value};
+
+use crate::lint::{Diagnostic, Rendered, Severity};
+
+/// Annotation holding a comma separated list of rules a document is exempt
+/// from
+const ALLOW_ANNOTATION: &str = "kluars.io/policy-allow";
+
+fn severity(s: &str) -> Result<Severity> {
+    match s {
+        "deny" => Ok(Severity::Error),
+        "warn" => Ok(Severity::Warning),
+        _ => bail!("unknown policy severity {s}, expected warn or deny"),
+    }
+}
+
+struct Rule<'lua> {
+    name: String,
+    severity: Severity,
+    check: Function<'lua>,
+}
+
+/// Turn the result of a rule into violations
+fn violations(result: Value, default: Severity) -> Result<Vec<(Severity, String)>> {
+    let violation = |value: Value| -> Result<(Severity, String)> {
+        match value {
+            Value::String(s) => Ok((default, s.to_str()?.to_string())),
+            Value::Table(t) => {
+                let severity = match t.get::<_, Option<String>>("severity")? {
+                    Some(s) => severity(&s)?,
+                    None => default,
+                };
+                Ok((severity, t.get("message")?))
+            }
+            _ => bail!("policy violations must be strings or tables"),
+        }
+    };
+
+    match result {
+        Value::Nil | Value::Boolean(false) => Ok(Vec::new()),
+        Value::Table(t) if !t.contains_key("message")? => t
+            .sequence_values::<Value>()
+            .map(|v| violation(v?))
+            .collect(),
+        value => Ok(vec![violation(value)?]),
+    }
+}
+
+/// Rules loaded from a policy directory
+pub(crate) struct Policies<'lua> {
+    rules: Vec<Rule<'lua>>,
+}
+
+impl<'lua> Policies<'lua> {
+    /// Load the rules of every lua file in a directory
+    pub fn load(lua: &'lua Lua, dir: &Path) -> Result<Self> {
+        let mut files: Vec<_> = fs::read_dir(dir)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<_, _>>()?;
+        files.retain(|f| f.extension().is_some_and(|e| e == "lua"));
+        files.sort();
+
+        let mut rules = Vec::new();
+        for file in files {
+            let table: Table = lua
+                .load(fs::read_to_string(&file)?)
+                .set_name(format!("@{}", file.display()))
+                .eval()?;
+            let mut named = table
+                .pairs::<String, Value>()
+                .collect::<mlua::Result<Vec<_>>>()?;
+            // Keep the order of rules stable
+            named.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+            for (name, rule) in named {
+                let rule = match rule {
+                    Value::Function(check) => Rule {
+                        name,
+                        severity: Severity::Error,
+                        check,
+                    },
+                    Value::Table(t) => Rule {
+                        severity: match t.get::<_, Option<String>>("severity")? {
+                            Some(s) => severity(&s)?,
+                            None => Severity::Error,
+                        },
+                        check: t.get("check")?,
+                        name,
+                    },
+                    _ => bail!("rule {name} in {} is not a function", file.display()),
+                };
+                rules.push(rule);
+            }
+        }
+        Ok(Policies { rules })
+    }
+
+    /// Run every rule on every document, skipping the rules a document is
+    /// explicitly allowed to break
+    pub fn check(&self, lua: &'lua Lua, docs: &[Rendered]) -> Result<Vec<Diagnostic>> {
+        let mut diagnostics = Vec::new();
+        for r in docs {
+            let allowed: Vec<&str> = r
+                .doc
+                .pointer("/metadata/annotations")
+                .and_then(|a| a.get(ALLOW_ANNOTATION))
+                .and_then(|a| a.as_str())
+                .map(|a| a.split(',').map(str::trim).collect())
+                .unwrap_or_default();
+
+            for rule in &self.rules {
+                if allowed.contains(&rule.name.as_str()) {
+                    continue;
+                }
+                let result = rule.check.call(lua.to_value(&r.doc)?)?;
+                for (severity, message) in violations(result, rule.severity)? {
+                    let message = format!("policy {}: {message}", rule.name);
+                    diagnostics.push(r.diagnostic(severity, message));
+                }
+            }
+        }
+        Ok(diagnostics)
+    }
+}
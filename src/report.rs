@@ -0,0 +1,138 @@
+//! Reporting of apply results
+
+use std::{fmt, time::Duration};
+
+use anyhow::Result;
+use kube::{api::DynamicObject, ResourceExt};
+use serde::Serialize;
+
+use crate::config::Output;
+
+/// What happened to an object during apply
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Action {
+    Created,
+    Configured,
+    Unchanged,
+    Pruned,
+    Failed,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self {
+            Action::Created => "created",
+            Action::Configured => "configured",
+            Action::Unchanged => "unchanged",
+            Action::Pruned => "pruned",
+            Action::Failed => "failed",
+        };
+        write!(f, "{action}")
+    }
+}
+
+/// Result of applying a single object, as printed with `--output`
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Outcome {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<String>,
+    pub api_version: String,
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    pub name: String,
+    pub action: Action,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resource_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generation: Option<i64>,
+    /// Time spent applying the object, including retries
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Outcome {
+    /// Build the outcome for an object returned by the API server
+    pub fn new(
+        context: Option<String>,
+        obj: &DynamicObject,
+        action: Action,
+        duration: Option<Duration>,
+    ) -> Self {
+        let types = obj.types.clone().unwrap_or_default();
+        Outcome {
+            context,
+            api_version: types.api_version,
+            kind: types.kind,
+            namespace: obj.namespace(),
+            name: obj.name_any(),
+            action,
+            resource_version: obj.resource_version(),
+            generation: obj.metadata.generation,
+            duration_ms: duration.map(|d| d.as_millis() as u64),
+            error: None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    results: &'a [Outcome],
+}
+
+/// Print the results of an apply run in the requested format
+pub(crate) fn print(outcomes: &[Outcome], output: Output) -> Result<()> {
+    let report = Report { results: outcomes };
+    match output {
+        Output::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+        Output::Yaml => print!("{}", serde_yaml::to_string(&report)?),
+    }
+    Ok(())
+}
+
+/// A document that could not be applied
+pub(crate) struct Failure {
+    pub api_version: String,
+    pub kind: String,
+    pub namespace: Option<String>,
+    pub name: String,
+    /// Lua file the document was rendered in
+    pub source: String,
+    pub error: anyhow::Error,
+}
+
+impl Failure {
+    pub fn outcome(&self, context: Option<String>, duration: Duration) -> Outcome {
+        Outcome {
+            context,
+            api_version: self.api_version.clone(),
+            kind: self.kind.clone(),
+            namespace: self.namespace.clone(),
+            name: self.name.clone(),
+            action: Action::Failed,
+            resource_version: None,
+            generation: None,
+            duration_ms: Some(duration.as_millis() as u64),
+            error: Some(format!("{:#}", self.error)),
+        }
+    }
+}
+
+/// Print a report of every document that failed to apply
+pub(crate) fn failures(failures: &[Failure]) {
+    eprintln!("Failed to apply {} documents:", failures.len());
+    for f in failures {
+        let path = match &f.namespace {
+            Some(ns) => format!("{ns}/{}", f.name),
+            None => f.name.clone(),
+        };
+        eprintln!(
+            "  {} {} {path} ({}): {:#}",
+            f.api_version, f.kind, f.source, f.error
+        );
+    }
+}
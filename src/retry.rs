@@ -0,0 +1,206 @@
+//! Retries with exponential backoff for transient API errors
+
+use std::{error::Error, fmt, future::Future, io, time::Duration};
+
+use anyhow::Result;
+use log::debug;
+use rand::Rng;
+
+/// Upper bound for the time between retries
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Error returned by the client when the API server asks to retry the
+/// request later through the `Retry-After` header
+#[derive(Debug)]
+pub(crate) struct Throttled {
+    pub code: u16,
+    pub retry_after: Duration,
+}
+
+impl fmt::Display for Throttled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "API server responded with {}, retry after {}s",
+            self.code,
+            self.retry_after.as_secs()
+        )
+    }
+}
+
+impl Error for Throttled {}
+
+/// Wraps an error that must not be retried, even if it usually would be
+#[derive(Debug)]
+pub(crate) struct Permanent(pub kube::Error);
+
+impl fmt::Display for Permanent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Error for Permanent {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.0.source()
+    }
+}
+
+/// Whether the server rejected a request without processing it, asking to
+/// try again later
+pub(crate) fn throttled(err: &kube::Error) -> bool {
+    match err {
+        kube::Error::Api(e) => e.code == 429,
+        kube::Error::Service(e) => e.is::<Throttled>(),
+        _ => false,
+    }
+}
+
+/// Whether an error is worth retrying, along with the delay requested by
+/// the server, if any
+fn retryable(err: &anyhow::Error) -> Option<Option<Duration>> {
+    match err.downcast_ref::<kube::Error>()? {
+        kube::Error::Api(e) => match e.code {
+            429 | 500 | 502 | 503 | 504 => Some(None),
+            // Field manager conflicts won't go away by retrying
+            409 if e.reason == "Conflict" && !e.message.starts_with("Apply failed") => Some(None),
+            _ => None,
+        },
+        kube::Error::Service(e) => match e.downcast_ref::<Throttled>() {
+            Some(throttled) => Some(Some(throttled.retry_after)),
+            None => transient(e.as_ref()).then_some(None),
+        },
+        kube::Error::HyperError(e) => transient(e).then_some(None),
+        _ => None,
+    }
+}
+
+/// Whether an error from the client stack was caused by a failed
+/// connection, a reset or a timeout, anything else like authentication
+/// failures won't go away by retrying
+fn transient(err: &(dyn Error + 'static)) -> bool {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if let Some(e) = err.downcast_ref::<io::Error>() {
+            return matches!(
+                e.kind(),
+                io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::NotConnected
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::UnexpectedEof
+            );
+        }
+        source = err.source();
+    }
+    false
+}
+
+/// Run `f` until it succeeds, fails with an error that can't be retried
+/// or runs out of retries. Returns the result along with the number of
+/// retries performed.
+pub(crate) async fn retry<F, Fut, T>(retries: u32, backoff: Duration, mut f: F) -> (Result<T>, u32)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut attempt = 0;
+    loop {
+        let err = match f().await {
+            Ok(r) => return (Ok(r), attempt),
+            Err(e) => e,
+        };
+        let Some(retry_after) = retryable(&err).filter(|_| attempt < retries) else {
+            return (Err(err), attempt);
+        };
+
+        // Exponential backoff with full jitter, unless the server told us
+        // how long to wait
+        let delay = retry_after.unwrap_or_else(|| {
+            let max = backoff
+                .saturating_mul(2u32.saturating_pow(attempt))
+                .min(MAX_BACKOFF);
+            max.mul_f64(rand::thread_rng().gen())
+        });
+        attempt += 1;
+        debug!("Retrying in {delay:?} ({attempt}/{retries}): {err:#}");
+        tokio::time::sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kube::core::ErrorResponse;
+
+    use super::*;
+
+    fn api(code: u16, reason: &str, message: &str) -> anyhow::Error {
+        kube::Error::Api(ErrorResponse {
+            status: "Failure".to_string(),
+            message: message.to_string(),
+            reason: reason.to_string(),
+            code,
+        })
+        .into()
+    }
+
+    fn service(err: impl Error + Send + Sync + 'static) -> anyhow::Error {
+        kube::Error::Service(Box::new(err)).into()
+    }
+
+    #[test]
+    fn conflicts() {
+        let err = api(
+            409,
+            "Conflict",
+            "Apply failed with 1 conflict: conflict with \"kubectl\": .spec.replicas",
+        );
+        assert_eq!(retryable(&err), None);
+
+        let err = api(409, "Conflict", "the object has been modified");
+        assert_eq!(retryable(&err), Some(None));
+    }
+
+    #[test]
+    fn throttling() {
+        let err = api(429, "TooManyRequests", "too many requests");
+        assert_eq!(retryable(&err), Some(None));
+
+        let err = service(Throttled {
+            code: 429,
+            retry_after: Duration::from_secs(5),
+        });
+        assert_eq!(retryable(&err), Some(Some(Duration::from_secs(5))));
+    }
+
+    #[test]
+    fn permanent() {
+        let err = Permanent(kube::Error::Api(ErrorResponse {
+            status: "Failure".to_string(),
+            message: "internal error".to_string(),
+            reason: "InternalError".to_string(),
+            code: 500,
+        }));
+        assert_eq!(retryable(&err.into()), None);
+    }
+
+    #[test]
+    fn connection() {
+        let err = service(io::Error::from(io::ErrorKind::ConnectionReset));
+        assert_eq!(retryable(&err), Some(None));
+
+        let err = service(io::Error::from(io::ErrorKind::TimedOut));
+        assert_eq!(retryable(&err), Some(None));
+
+        let err = service(io::Error::new(
+            io::ErrorKind::Other,
+            "exec plugin failed to get credentials",
+        ));
+        assert_eq!(retryable(&err), None);
+
+        let err = service(fmt::Error);
+        assert_eq!(retryable(&err), None);
+    }
+}
@@ -0,0 +1,451 @@
+//! Checks of rendered documents against the types in k8s-openapi and the
+//! schemas of CustomResourceDefinitions
+
+use std::fmt;
+
+use k8s_openapi::{
+    api::{
+        admissionregistration, apiserverinternal, apps, autoscaling, batch, certificates,
+        coordination, core, discovery, events, flowcontrol, networking, node, policy, rbac,
+        resource, scheduling, storage,
+    },
+    apiextensions_apiserver::pkg::apis::apiextensions::{
+        self,
+        v1::{
+            CustomResourceDefinition, JSONSchemaProps, JSONSchemaPropsOrArray,
+            JSONSchemaPropsOrBool,
+        },
+    },
+    kube_aggregator::pkg::apis::apiregistration,
+    Resource,
+};
+use log::debug;
+use mlua::{DeserializeOptions, Lua, LuaSerdeExt, Table};
+use regex::Regex;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use serde_path_to_error::Segment;
+
+use crate::CLUSTER_FIELD;
+
+/// A k8s-openapi type documents can be checked against
+pub(crate) struct Type {
+    pub api_version: &'static str,
+    pub group: &'static str,
+    pub version: &'static str,
+    pub kind: &'static str,
+    check: fn(Value) -> Result<Value, Issue>,
+}
+
+macro_rules! types {
+    ($($ty:ty),* $(,)?) => {
+        &[$(Type {
+            api_version: <$ty as Resource>::API_VERSION,
+            group: <$ty as Resource>::GROUP,
+            version: <$ty as Resource>::VERSION,
+            kind: <$ty as Resource>::KIND,
+            check: roundtrip::<$ty>,
+        }),*]
+    };
+}
+
+/// Every object type in k8s-openapi for the k8s version it's built for,
+/// this needs to be kept in sync when upgrading it. Custom resources and
+/// anything not listed here are not checked
+pub(crate) const TYPES: &[Type] = types![
+    admissionregistration::v1::MutatingWebhookConfiguration,
+    admissionregistration::v1::ValidatingWebhookConfiguration,
+    admissionregistration::v1alpha1::ValidatingAdmissionPolicy,
+    admissionregistration::v1alpha1::ValidatingAdmissionPolicyBinding,
+    admissionregistration::v1beta1::ValidatingAdmissionPolicy,
+    admissionregistration::v1beta1::ValidatingAdmissionPolicyBinding,
+    apiserverinternal::v1alpha1::StorageVersion,
+    apps::v1::ControllerRevision,
+    apps::v1::DaemonSet,
+    apps::v1::Deployment,
+    apps::v1::ReplicaSet,
+    apps::v1::StatefulSet,
+    autoscaling::v1::HorizontalPodAutoscaler,
+    autoscaling::v2::HorizontalPodAutoscaler,
+    batch::v1::CronJob,
+    batch::v1::Job,
+    certificates::v1::CertificateSigningRequest,
+    certificates::v1alpha1::ClusterTrustBundle,
+    coordination::v1::Lease,
+    core::v1::ComponentStatus,
+    core::v1::ConfigMap,
+    core::v1::Endpoints,
+    core::v1::Event,
+    core::v1::LimitRange,
+    core::v1::Namespace,
+    core::v1::Node,
+    core::v1::PersistentVolume,
+    core::v1::PersistentVolumeClaim,
+    core::v1::Pod,
+    core::v1::PodTemplate,
+    core::v1::ReplicationController,
+    core::v1::ResourceQuota,
+    core::v1::Secret,
+    core::v1::Service,
+    core::v1::ServiceAccount,
+    discovery::v1::EndpointSlice,
+    events::v1::Event,
+    flowcontrol::v1::FlowSchema,
+    flowcontrol::v1::PriorityLevelConfiguration,
+    flowcontrol::v1beta3::FlowSchema,
+    flowcontrol::v1beta3::PriorityLevelConfiguration,
+    networking::v1::Ingress,
+    networking::v1::IngressClass,
+    networking::v1::NetworkPolicy,
+    networking::v1alpha1::IPAddress,
+    networking::v1alpha1::ServiceCIDR,
+    node::v1::RuntimeClass,
+    policy::v1::PodDisruptionBudget,
+    rbac::v1::ClusterRole,
+    rbac::v1::ClusterRoleBinding,
+    rbac::v1::Role,
+    rbac::v1::RoleBinding,
+    resource::v1alpha2::PodSchedulingContext,
+    resource::v1alpha2::ResourceClaim,
+    resource::v1alpha2::ResourceClaimTemplate,
+    resource::v1alpha2::ResourceClass,
+    scheduling::v1::PriorityClass,
+    storage::v1::CSIDriver,
+    storage::v1::CSINode,
+    storage::v1::CSIStorageCapacity,
+    storage::v1::StorageClass,
+    storage::v1::VolumeAttachment,
+    storage::v1alpha1::VolumeAttributesClass,
+    apiextensions::v1::CustomResourceDefinition,
+    apiregistration::v1::APIService,
+];
+
+/// A problem found in a document, along with the path to the offending
+/// field, e.g. `spec.template.spec.containers[0].name`
+pub(crate) struct Issue {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() || self.path == "." {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+/// Keys holding a map of resource quantities, e.g. `resources.limits`
+const QUANTITY_MAPS: &[&str] = &[
+    "allocatable",
+    "capacity",
+    "default",
+    "defaultRequest",
+    "hard",
+    "limits",
+    "max",
+    "maxLimitRequestRatio",
+    "min",
+    "overhead",
+    "requests",
+];
+
+/// Keys holding a single resource quantity
+const QUANTITY_FIELDS: &[&str] = &["averageValue", "sizeLimit"];
+
+/// Whether a field holds a `Quantity`, those are strings in k8s-openapi but
+/// the API server also accepts numbers for them, e.g. `cpu: 1`
+fn quantity(path: &serde_path_to_error::Path) -> bool {
+    let keys: Vec<&str> = path
+        .iter()
+        .map(|segment| match segment {
+            Segment::Map { key } => key.as_str(),
+            _ => "",
+        })
+        .collect();
+    match keys[..] {
+        [.., "target", "value"] => true,
+        [.., parent, _] if QUANTITY_MAPS.contains(&parent) => true,
+        [.., last] => QUANTITY_FIELDS.contains(&last),
+        [] => false,
+    }
+}
+
+/// Get the value a path points to
+fn value_at<'a>(doc: &'a mut Value, path: &serde_path_to_error::Path) -> Option<&'a mut Value> {
+    path.iter().try_fold(doc, |value, segment| match segment {
+        Segment::Map { key } => value.get_mut(key),
+        Segment::Seq { index } => value.get_mut(index),
+        _ => None,
+    })
+}
+
+/// Deserialize a document into its type and back, any field missing after
+/// the round trip is not part of the type
+fn roundtrip<T: DeserializeOwned + Serialize>(mut doc: Value) -> Result<Value, Issue> {
+    let obj: T = loop {
+        let err = match serde_path_to_error::deserialize(doc.clone()) {
+            Ok(obj) => break obj,
+            Err(err) => err,
+        };
+        // Numeric quantities are turned into strings, as the API server does
+        if quantity(err.path()) {
+            if let Some(value) = value_at(&mut doc, err.path()).filter(|v| v.is_number()) {
+                *value = Value::String(value.to_string());
+                continue;
+            }
+        }
+        return Err(Issue {
+            path: err.path().to_string(),
+            message: err.into_inner().to_string(),
+        });
+    };
+    serde_json::to_value(obj).map_err(|e| Issue {
+        path: String::new(),
+        message: e.to_string(),
+    })
+}
+
+fn unknown_fields(doc: &Value, typed: &Value, path: &str, issues: &mut Vec<Issue>) {
+    match (doc, typed) {
+        (Value::Object(doc), Value::Object(typed)) => {
+            for (key, value) in doc {
+                let path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{path}.{key}")
+                };
+                match typed.get(key) {
+                    Some(typed) => unknown_fields(value, typed, &path, issues),
+                    None => issues.push(Issue {
+                        path,
+                        message: "unknown field".to_string(),
+                    }),
+                }
+            }
+        }
+        (Value::Array(doc), Value::Array(typed)) => {
+            for (i, (value, typed)) in doc.iter().zip(typed).enumerate() {
+                unknown_fields(value, typed, &format!("{path}[{i}]"), issues);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Convert a document rendered by lua into JSON, leaving out the fields
+/// that are not part of the k8s object
+pub(crate) fn document(lua: &Lua, table: &Table) -> mlua::Result<Value> {
+    // Functions like custom health checks are skipped
+    let options = DeserializeOptions::new().deny_unsupported_types(false);
+    let mut doc: Value = lua.from_value_with(mlua::Value::Table(table.clone()), options)?;
+    if let Some(doc) = doc.as_object_mut() {
+        doc.remove(CLUSTER_FIELD);
+    }
+    Ok(doc)
+}
+
+/// Find the type for an apiVersion and kind
+pub(crate) fn find(api_version: &str, kind: &str) -> Option<&'static Type> {
+    TYPES
+        .iter()
+        .find(|t| t.api_version == api_version && t.kind == kind)
+}
+
+/// Check a document against the k8s-openapi type matching its apiVersion
+/// and kind, returns `None` for types not in k8s-openapi.
+pub(crate) fn validate(doc: &Value) -> Option<Vec<Issue>> {
+    let api_version = doc.get("apiVersion").and_then(Value::as_str);
+    let kind = doc.get("kind").and_then(Value::as_str);
+    let ty = api_version.zip(kind).and_then(|(a, k)| find(a, k))?;
+
+    match (ty.check)(doc.clone()) {
+        Ok(typed) => {
+            let mut issues = Vec::new();
+            unknown_fields(doc, &typed, "", &mut issues);
+            Some(issues)
+        }
+        Err(issue) => Some(vec![issue]),
+    }
+}
+
+/// Schema of a custom resource version
+struct Custom {
+    api_version: String,
+    kind: String,
+    schema: JSONSchemaProps,
+}
+
+/// Schemas documents are checked against, built-in types are always known
+/// while custom resources need their CRDs to be added
+#[derive(Default)]
+pub(crate) struct Schemas {
+    custom: Vec<Custom>,
+}
+
+impl Schemas {
+    /// Add the schema of every version served by a CRD
+    pub fn add(&mut self, crd: &CustomResourceDefinition) {
+        for version in &crd.spec.versions {
+            let schema = version
+                .schema
+                .as_ref()
+                .and_then(|s| s.open_api_v3_schema.clone());
+            let Some(schema) = schema else {
+                continue;
+            };
+            self.custom.push(Custom {
+                api_version: format!("{}/{}", crd.spec.group, version.name),
+                kind: crd.spec.names.kind.clone(),
+                schema,
+            });
+        }
+    }
+
+    /// Check a document against the schema for its type, returns `None` if
+    /// there is no schema for it
+    pub fn validate(&self, doc: &Value) -> Option<Vec<Issue>> {
+        if let Some(issues) = validate(doc) {
+            return Some(issues);
+        }
+
+        let api_version = doc.get("apiVersion").and_then(Value::as_str)?;
+        let kind = doc.get("kind").and_then(Value::as_str)?;
+        let custom = self
+            .custom
+            .iter()
+            .find(|c| c.api_version == api_version && c.kind == kind)?;
+
+        let mut issues = Vec::new();
+        check(&custom.schema, doc, "", &mut issues);
+        Some(issues)
+    }
+}
+
+fn field(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_f64() => "number",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Check a value against an OpenAPI v3 schema from a CRD
+fn check(schema: &JSONSchemaProps, value: &Value, path: &str, issues: &mut Vec<Issue>) {
+    let mut issue = |message: String| {
+        issues.push(Issue {
+            path: path.to_string(),
+            message,
+        })
+    };
+
+    if value.is_null() && schema.nullable == Some(true) {
+        return;
+    }
+    if schema.x_kubernetes_int_or_string == Some(true) {
+        if !value.is_string() && !value.is_i64() && !value.is_u64() {
+            issue(format!(
+                "expected integer or string, found {}",
+                type_name(value)
+            ));
+        }
+        return;
+    }
+
+    if let Some(ty) = &schema.type_ {
+        let valid = match ty.as_str() {
+            "object" => value.is_object(),
+            "array" => value.is_array(),
+            "string" => value.is_string(),
+            "integer" => value.is_i64() || value.is_u64(),
+            "number" => value.is_number(),
+            "boolean" => value.is_boolean(),
+            _ => true,
+        };
+        if !valid {
+            issue(format!("expected {ty}, found {}", type_name(value)));
+            return;
+        }
+    }
+
+    if let Some(allowed) = &schema.enum_ {
+        if !allowed.iter().any(|v| &v.0 == value) {
+            let allowed: Vec<String> = allowed.iter().map(|v| v.0.to_string()).collect();
+            issue(format!(
+                "unsupported value {value}, expected one of {}",
+                allowed.join(", ")
+            ));
+        }
+    }
+
+    if let (Some(pattern), Some(s)) = (&schema.pattern, value.as_str()) {
+        match Regex::new(pattern) {
+            Ok(re) if !re.is_match(s) => issue(format!("{value} does not match {pattern}")),
+            Ok(_) => {}
+            Err(e) => debug!("Skipping unsupported pattern {pattern}: {e}"),
+        }
+    }
+
+    match value {
+        Value::Object(map) => {
+            for key in schema.required.iter().flatten() {
+                if !map.contains_key(key) {
+                    issues.push(Issue {
+                        path: field(path, key),
+                        message: "missing required field".to_string(),
+                    });
+                }
+            }
+
+            // The type and metadata of objects are not part of their schema
+            let embedded = path.is_empty() || schema.x_kubernetes_embedded_resource == Some(true);
+            let preserve = schema.x_kubernetes_preserve_unknown_fields == Some(true);
+            for (key, value) in map {
+                let path = field(path, key);
+                if embedded && key == "metadata" {
+                    // Checked by the API server as any other ObjectMeta
+                    continue;
+                }
+                if let Some(props) = schema.properties.as_ref().and_then(|p| p.get(key)) {
+                    check(props, value, &path, issues);
+                    continue;
+                }
+                match &schema.additional_properties {
+                    Some(JSONSchemaPropsOrBool::Schema(props)) => {
+                        check(props, value, &path, issues)
+                    }
+                    Some(JSONSchemaPropsOrBool::Bool(true)) => {}
+                    _ if preserve => {}
+                    _ if embedded && ["apiVersion", "kind", "metadata"].contains(&key.as_str()) => {
+                    }
+                    _ => issues.push(Issue {
+                        path,
+                        message: "unknown field".to_string(),
+                    }),
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(JSONSchemaPropsOrArray::Schema(props)) = &schema.items {
+                for (i, item) in items.iter().enumerate() {
+                    check(props, item, &format!("{path}[{i}]"), issues);
+                }
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,66 @@
+//! Suggestions for resource types unknown to the cluster
+
+use kube::{core::GroupVersionKind, Discovery};
+
+/// Edit distance between two strings, ignoring case
+pub(crate) fn levenshtein(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.to_lowercase().chars().collect();
+    let b: Vec<char> = b.to_lowercase().chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.iter().enumerate() {
+        let mut curr = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != cb);
+            curr.push((prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1));
+        }
+        prev = curr;
+    }
+    prev[b.len()]
+}
+
+fn api_version(gvk: &GroupVersionKind) -> String {
+    if gvk.group.is_empty() {
+        gvk.version.clone()
+    } else {
+        format!("{}/{}", gvk.group, gvk.version)
+    }
+}
+
+/// Find the known apiVersion and kind closest to an unknown GVK, mismatches
+/// in the kind weigh more than ones in the apiVersion.
+pub(crate) fn closest(discovery: &Discovery, gvk: &GroupVersionKind) -> Option<(String, String)> {
+    let wanted = api_version(gvk);
+    let max_distance = (gvk.kind.len() / 3).max(2);
+
+    discovery
+        .groups()
+        .flat_map(|group| {
+            group
+                .versions()
+                .flat_map(|v| group.versioned_resources(v))
+                .collect::<Vec<_>>()
+        })
+        .filter_map(|(ar, _)| {
+            let distance = levenshtein(&gvk.kind, &ar.kind);
+            if distance > max_distance {
+                return None;
+            }
+            let score = distance * 100 + levenshtein(&wanted, &ar.api_version);
+            Some((score, ar.api_version, ar.kind))
+        })
+        .min()
+        .map(|(_, api_version, kind)| (api_version, kind))
+}
+
+/// Print a summary of the documents that could not be resolved
+pub(crate) fn report(discovery: &Discovery, unresolved: &[(GroupVersionKind, String)]) {
+    eprintln!("Documents with unknown resource types were not applied:");
+    for (gvk, name) in unresolved {
+        let hint = match closest(discovery, gvk) {
+            Some((api_version, kind)) => format!(", did you mean {api_version} {kind}?"),
+            None => String::new(),
+        };
+        eprintln!("  {} {} {}{}", api_version(gvk), gvk.kind, name, hint);
+    }
+}
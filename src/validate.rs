@@ -0,0 +1,101 @@
+//! Offline checks of the documents rendered by lua scripts
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
+use kube::{api::ListParams, Api};
+use mlua::Lua;
+use serde::Deserialize;
+
+use crate::{
+    client, clusters,
+    config::{KubeArgs, ValidateArgs},
+    lint,
+    policy::Policies,
+    rendered, run_lua,
+    schema::Schemas,
+    set_env,
+};
+
+/// Value of `--crd` used to load CRDs from the cluster
+const CLUSTER: &str = "cluster";
+
+/// Get the YAML files in a directory and its subdirectories
+fn manifests(path: &Path) -> Result<Vec<PathBuf>> {
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(manifests(&path)?);
+        } else if matches!(
+            path.extension().and_then(|e| e.to_str()),
+            Some("yaml" | "yml")
+        ) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Load the CRDs in a manifest or directory, or from the cluster itself
+async fn load(source: &str, kube: &KubeArgs) -> Result<Vec<CustomResourceDefinition>> {
+    let path = Path::new(source);
+    if source == CLUSTER && !path.exists() {
+        let api: Api<CustomResourceDefinition> = Api::all(client::client(kube).await?);
+        return Ok(api.list(&ListParams::default()).await?.items);
+    }
+
+    let mut crds = Vec::new();
+    for file in manifests(path)? {
+        let content = fs::read_to_string(&file)?;
+        for doc in serde_yaml::Deserializer::from_str(&content) {
+            let value = serde_yaml::Value::deserialize(doc)?;
+            if value.get("kind").and_then(|k| k.as_str()) == Some("CustomResourceDefinition") {
+                crds.push(serde_yaml::from_value(value)?);
+            }
+        }
+    }
+    Ok(crds)
+}
+
+/// Check the rendered documents against the schemas of built-in types and
+/// the given CRDs, along with structural checks that don't need a cluster.
+/// Fails when any error is found, warnings are only printed.
+pub(crate) async fn validate(args: ValidateArgs) -> Result<()> {
+    set_env(&args.global.lua_args);
+    let lua = Lua::new();
+    let table = run_lua(&lua, &args.global)?;
+    let clusters = clusters(&lua, table, &args.global.kube)?;
+
+    let mut schemas = Schemas::default();
+    for source in &args.crd {
+        for crd in load(source, &args.global.kube).await? {
+            schemas.add(&crd);
+        }
+    }
+
+    let policies = match &args.global.policy {
+        Some(dir) => Some(Policies::load(&lua, dir)?),
+        None => None,
+    };
+
+    let mut diagnostics = Vec::new();
+    for cluster in &clusters {
+        let docs = rendered(&lua, cluster, &args.global.lua_args)?;
+        diagnostics.extend(lint::schema(&docs, &schemas));
+        diagnostics.extend(lint::lint(&docs, args.global.namespace.as_deref()));
+        if let Some(policies) = &policies {
+            diagnostics.extend(policies.check(&lua, &docs)?);
+        }
+    }
+    lint::report(&diagnostics)
+}
@@ -482,3 +482,292 @@ fn multidoc() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn health_check_stripped() -> Result<()> {
+    let output = Command::cargo_bin("kluars")?
+        .args(["xlate", "lua/certificate.lua"])
+        .output()?;
+    assert!(output.status.success());
+    let out = String::from_utf8(output.stdout)?;
+    let out: HashMap<String, Value> = serde_yaml::from_str(&out).expect("Got invalid YAML");
+
+    assert!(!out.contains_key("__health"));
+
+    // kind: Certificate
+    let value = out.get("kind").expect("kind not found");
+    let expected = &Value::String(String::from("Certificate"));
+    assert_eq!(value, expected);
+
+    let spec = out.get("spec").expect("did not find spec");
+    let value = spec.get("secretName").expect("no secretName in spec");
+    let expected = &Value::String(String::from("example-com-tls"));
+    assert_eq!(value, expected);
+
+    Ok(())
+}
+
+#[test]
+fn kubeconfig_context() -> Result<()> {
+    let output = Command::cargo_bin("kluars")?
+        .args([
+            "xlate",
+            "--kubeconfig",
+            "tests/data/kubeconfig.yaml",
+            "--context",
+            "prod",
+            "lua/context.lua",
+        ])
+        .output()?;
+    assert!(output.status.success());
+    let out = String::from_utf8(output.stdout)?;
+    let out: HashMap<String, Value> = serde_yaml::from_str(&out).expect("Got invalid YAML");
+
+    let metadata = out.get("metadata").expect("did not find metadata");
+    let value = metadata.get("namespace").expect("did not find namespace");
+    let expected = &Value::String(String::from("production"));
+    assert_eq!(value, expected);
+
+    let data = out.get("data").expect("did not find data");
+    let value = data.get("context").expect("no context in data");
+    let expected = &Value::String(String::from("prod"));
+    assert_eq!(value, expected);
+
+    let value = data.get("cluster").expect("no cluster in data");
+    let expected = &Value::String(String::from("prod-cluster"));
+    assert_eq!(value, expected);
+
+    // Without --context the current context is used
+    let output = Command::cargo_bin("kluars")?
+        .args([
+            "xlate",
+            "--kubeconfig",
+            "tests/data/kubeconfig.yaml",
+            "lua/context.lua",
+        ])
+        .output()?;
+    assert!(output.status.success());
+    let out = String::from_utf8(output.stdout)?;
+    let out: HashMap<String, Value> = serde_yaml::from_str(&out).expect("Got invalid YAML");
+
+    let data = out.get("data").expect("did not find data");
+    let value = data.get("context").expect("no context in data");
+    let expected = &Value::String(String::from("staging"));
+    assert_eq!(value, expected);
+
+    let value = data.get("cluster").expect("no cluster in data");
+    let expected = &Value::String(String::from("staging-cluster"));
+    assert_eq!(value, expected);
+
+    Ok(())
+}
+
+#[test]
+fn multi_cluster() -> Result<()> {
+    let output = Command::cargo_bin("kluars")?
+        .args(["xlate", "lua/multi-cluster.lua"])
+        .output()?;
+    assert!(output.status.success());
+    let out = String::from_utf8(output.stdout)?;
+
+    // Contexts are sorted by name
+    let eu = out.find("# context: prod-eu").expect("no prod-eu context");
+    let us = out.find("# context: prod-us").expect("no prod-us context");
+    assert!(eu < us);
+
+    let mut out = serde_yaml::Deserializer::from_str(&out);
+    let expected = ["eu", "us"];
+    for region in expected {
+        let config = out.next().expect("missing document");
+        let config: HashMap<String, Value> = deserialize(config)?;
+
+        let value = config.get("kind").expect("kind not found");
+        let expected = &Value::String(String::from("ConfigMap"));
+        assert_eq!(value, expected);
+
+        let data = config.get("data").expect("data not found");
+        let value = data.get("region").expect("no region in data");
+        let expected = &Value::String(String::from(region));
+        assert_eq!(value, expected);
+    }
+    assert!(out.next().is_none());
+
+    Ok(())
+}
+
+#[test]
+fn typed_constructor() -> Result<()> {
+    let output = Command::cargo_bin("kluars")?
+        .args(["xlate", "lua/typed.lua"])
+        .output()?;
+    assert!(output.status.success());
+    let out = String::from_utf8(output.stdout)?;
+    let out: HashMap<String, Value> = serde_yaml::from_str(&out).expect("Got invalid YAML");
+
+    // kind: Pod
+    let value = out.get("kind").expect("kind not found");
+    let expected = &Value::String(String::from("Pod"));
+    assert_eq!(value, expected);
+
+    // apiVersion: v1
+    let value = out.get("apiVersion").expect("apiVersion not found");
+    let expected = &Value::String(String::from("v1"));
+    assert_eq!(value, expected);
+
+    Ok(())
+}
+
+#[test]
+fn typed_unknown_field() -> Result<()> {
+    let output = Command::cargo_bin("kluars")?
+        .args(["xlate", "tests/data/typo.lua"])
+        .output()?;
+    assert!(!output.status.success());
+    let err = String::from_utf8(output.stderr)?;
+    assert!(err.contains("tests/data/typo.lua:3:"));
+    assert!(err.contains("spec.replica: unknown field"));
+
+    Ok(())
+}
+
+#[test]
+fn validate_unknown_field() -> Result<()> {
+    let output = Command::cargo_bin("kluars")?
+        .args(["xlate", "--validate", "tests/data/invalid.lua"])
+        .output()?;
+    assert!(!output.status.success());
+    let err = String::from_utf8(output.stderr)?;
+    let expected = "tests/data/invalid.lua: error: v1 Pod nginx: \
+                    spec.containers[0].ports[0].contanerPort: unknown field";
+    assert!(err.contains(expected));
+
+    let output = Command::cargo_bin("kluars")?
+        .args(["xlate", "--validate", "lua/nginx-app/"])
+        .output()?;
+    assert!(output.status.success());
+
+    Ok(())
+}
+
+#[test]
+fn validate_crd() -> Result<()> {
+    let output = Command::cargo_bin("kluars")?
+        .args([
+            "validate",
+            "--crd",
+            "tests/data/crds/",
+            "tests/data/certificate.lua",
+        ])
+        .output()?;
+    assert!(!output.status.success());
+    let err = String::from_utf8(output.stderr)?;
+    assert!(err.contains("spec.secretName: missing required field"));
+    assert!(err.contains("spec.dnsName: unknown field"));
+    assert!(err.contains("spec.privateKey.algorithm: unsupported value \"DSA\""));
+
+    let output = Command::cargo_bin("kluars")?
+        .args([
+            "validate",
+            "--crd",
+            "tests/data/crds/certificates.yaml",
+            "lua/certificate.lua",
+        ])
+        .output()?;
+    assert!(output.status.success());
+
+    Ok(())
+}
+
+#[test]
+fn validate_lint() -> Result<()> {
+    let output = Command::cargo_bin("kluars")?
+        .args(["validate", "tests/data/lint.lua"])
+        .output()?;
+    assert!(!output.status.success());
+    let err = String::from_utf8(output.stderr)?;
+
+    let expected = [
+        "error: apps/v1 Deployment Web_Server: metadata.name: `Web_Server` must consist",
+        "error: apps/v1 Deployment Web_Server: spec.selector does not match",
+        "error: v1 ConfigMap web: metadata.labels.-invalid: `-invalid` must consist",
+        "error: v1 ConfigMap web: duplicate object",
+        "lint.lua: error: v1 ConfigMap: missing required field metadata.name",
+        "lint.lua:96: error: v1 ConfigMap Typed_Config: metadata.name: `Typed_Config` must consist",
+        "error: v1 Service web: spec.ports[0]: no selected pod has a port named https",
+        "warning: v1 Service web: spec.ports[1]: no selected pod exposes port 8080",
+        "warning: v1 Service orphan: spec.selector matches no pods",
+        "error: v1 Service 1web: metadata.name: `1web` must start with a lower case letter",
+    ];
+    for expected in expected {
+        assert!(err.contains(expected), "missing diagnostic: {expected}");
+    }
+
+    let output = Command::cargo_bin("kluars")?
+        .args(["validate", "lua/nginx-app/"])
+        .output()?;
+    assert!(output.status.success());
+
+    Ok(())
+}
+
+#[test]
+fn policy() -> Result<()> {
+    let output = Command::cargo_bin("kluars")?
+        .args([
+            "xlate",
+            "--policy",
+            "lua/policies/",
+            "tests/data/policy.lua",
+        ])
+        .output()?;
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let err = String::from_utf8(output.stderr)?;
+
+    let expected = [
+        "error: v1 Pod latest: policy no-latest-tag: container web uses image nginx:latest",
+        "warning: v1 Pod latest: policy resource-limits: container web has no resource limits",
+        "error: v1 Pod host: policy no-host-network: pods must not use the host network",
+    ];
+    for expected in expected {
+        assert!(err.contains(expected), "missing diagnostic: {expected}");
+    }
+    // Exempt through the allowlist annotation
+    assert!(!err.contains("v1 Pod allowed"));
+
+    // Warnings alone don't fail
+    let output = Command::cargo_bin("kluars")?
+        .args(["validate", "--policy", "lua/policies/", "lua/nginx-app/"])
+        .output()?;
+    assert!(output.status.success());
+    let err = String::from_utf8(output.stderr)?;
+    assert!(err.contains("warning: apps/v1 Deployment my-nginx: policy resource-limits"));
+
+    Ok(())
+}
+
+#[test]
+fn validate_quantities() -> Result<()> {
+    let output = Command::cargo_bin("kluars")?
+        .args(["xlate", "--validate", "tests/data/quantities.lua"])
+        .output()?;
+    assert!(output.status.success());
+
+    Ok(())
+}
+
+#[test]
+fn multi_cluster_needs_marker() -> Result<()> {
+    let output = Command::cargo_bin("kluars")?
+        .args(["xlate", "tests/data/no-api-version.lua"])
+        .output()?;
+    assert!(output.status.success());
+    let out = String::from_utf8(output.stdout)?;
+    assert!(!out.contains("# context:"));
+
+    let out: HashMap<String, Value> = serde_yaml::from_str(&out).expect("Got invalid YAML");
+    assert!(out.contains_key("metadata"));
+    assert!(out.contains_key("data"));
+
+    Ok(())
+}
@@ -0,0 +1,16 @@
+return {
+    apiVersion = 'cert-manager.io/v1',
+    kind = 'Certificate',
+    metadata = {
+        name = 'example-com',
+    },
+    spec = {
+        dnsName = 'example.com',
+        issuerRef = {
+            name = 'letsencrypt',
+        },
+        privateKey = {
+            algorithm = 'DSA',
+        },
+    },
+}
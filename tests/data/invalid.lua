@@ -0,0 +1,18 @@
+return {
+    apiVersion = 'v1',
+    kind = 'Pod',
+    metadata = {
+        name = 'nginx',
+    },
+    spec = {
+        containers = {
+            {
+                name = 'nginx',
+                image = 'nginx:1.14.2',
+                ports = {
+                    { contanerPort = 80, }
+                },
+            },
+        },
+    },
+}
@@ -0,0 +1,117 @@
+-- Documents with structural problems caught by `kluars validate`
+
+local k8s = require('k8s')
+
+local labels = {
+    app = 'web',
+}
+
+local deployment = {
+    apiVersion = 'apps/v1',
+    kind = 'Deployment',
+    metadata = {
+        name = 'Web_Server',
+    },
+    spec = {
+        selector = {
+            matchLabels = {
+                app = 'frontend',
+            },
+        },
+        template = {
+            metadata = {
+                labels = labels,
+            },
+            spec = {
+                containers = {
+                    {
+                        name = 'web',
+                        image = 'nginx',
+                        ports = {
+                            { name = 'http', containerPort = 80 },
+                        },
+                    },
+                },
+            },
+        },
+    },
+}
+
+local service = {
+    apiVersion = 'v1',
+    kind = 'Service',
+    metadata = {
+        name = 'web',
+    },
+    spec = {
+        selector = labels,
+        ports = {
+            { port = 80, targetPort = 'https' },
+            { port = 8080 },
+        },
+    },
+}
+
+local orphan = {
+    apiVersion = 'v1',
+    kind = 'Service',
+    metadata = {
+        name = 'orphan',
+    },
+    spec = {
+        selector = {
+            app = 'missing',
+        },
+        ports = {
+            { port = 80 },
+        },
+    },
+}
+
+local numeric = {
+    apiVersion = 'v1',
+    kind = 'Service',
+    metadata = {
+        name = '1web',
+    },
+    spec = {
+        selector = labels,
+        ports = {
+            { port = 80 },
+        },
+    },
+}
+
+local config = {
+    apiVersion = 'v1',
+    kind = 'ConfigMap',
+    metadata = {
+        name = 'web',
+        labels = {
+            ['-invalid'] = 'value',
+        },
+    },
+}
+
+local typed = k8s.core.v1.ConfigMap {
+    metadata = {
+        name = 'Typed_Config',
+    },
+}
+
+local unnamed = {
+    apiVersion = 'v1',
+    kind = 'ConfigMap',
+    metadata = {},
+}
+
+return {
+    deployment,
+    service,
+    orphan,
+    numeric,
+    config,
+    config,
+    unnamed,
+    typed,
+}
@@ -0,0 +1,9 @@
+-- Not keyed by context, it only misses its apiVersion and kind
+return {
+    metadata = {
+        name = 'app-config',
+    },
+    data = {
+        region = 'eu',
+    },
+}
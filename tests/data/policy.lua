@@ -0,0 +1,41 @@
+local function pod(name, spec, annotations)
+    return {
+        apiVersion = 'v1',
+        kind = 'Pod',
+        metadata = {
+            name = name,
+            annotations = annotations,
+        },
+        spec = spec,
+    }
+end
+
+return {
+    pod('latest', {
+        containers = {
+            { name = 'web', image = 'nginx:latest' },
+        },
+    }),
+    pod('host', {
+        hostNetwork = true,
+        containers = {
+            {
+                name = 'web',
+                image = 'nginx:1.14.2',
+                resources = { limits = { memory = '128Mi' } },
+            },
+        },
+    }),
+    pod('allowed', {
+        hostNetwork = true,
+        containers = {
+            {
+                name = 'web',
+                image = 'nginx:1.14.2',
+                resources = { limits = { memory = '128Mi' } },
+            },
+        },
+    }, {
+        ['kluars.io/policy-allow'] = 'no-host-network',
+    }),
+}
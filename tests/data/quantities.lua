@@ -0,0 +1,20 @@
+-- Quantities can be given as numbers, as the API server accepts them
+return {
+    apiVersion = 'v1',
+    kind = 'Pod',
+    metadata = {
+        name = 'nginx',
+    },
+    spec = {
+        containers = {
+            {
+                name = 'nginx',
+                image = 'nginx:1.14.2',
+                resources = {
+                    limits = { cpu = 1, memory = '128Mi' },
+                    requests = { cpu = 0.5 },
+                },
+            },
+        },
+    },
+}
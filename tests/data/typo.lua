@@ -0,0 +1,12 @@
+local k8s = require('k8s')
+
+local deployment = k8s.apps.v1.Deployment {
+    metadata = {
+        name = 'nginx',
+    },
+    spec = {
+        replica = 3,
+    },
+}
+
+return deployment